@@ -0,0 +1,501 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bytecode::OpCode;
+use crate::objects::ProgramObject;
+use crate::program::{Code, Program};
+use crate::types::{ConstantPoolIndex, LocalFrameIndex, Arity, Size, AddressRange};
+
+/// Parses the listing produced by `debug::PrettyPrint` back into a `Program`.
+///
+/// Method bodies are laid out in `Code` one after another in the order in which the methods
+/// appear in the constant pool, which is also how the pretty printer walks them, so a listing
+/// printed from a program whose methods are stored that way reassembles into an equal program.
+///
+/// `compiler::compile` instead places function and method bodies inside the range of the method
+/// that defines them, between `goto` and `label` instructions that jump over them, so that the
+/// listing shows such a body once under each method covering it. A method whose body appears
+/// behind such a guard in a longer method is given the addresses of that copy rather than a
+/// copy of its own; see `share_nested_code`.
+pub fn assemble(source: &str) -> Result<Program, AssemblyError> {
+    Assembler::new(source).program()
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+struct Assembler<'a> {
+    lines: Vec<(usize, &'a str)>,
+    position: usize,
+    code: Vec<OpCode>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(source: &'a str) -> Self {
+        let lines = source.lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line))
+            .filter(|(_, line)| !line.trim().is_empty())
+            .collect();
+        Assembler { lines, position: 0, code: Vec::new() }
+    }
+
+    fn program(mut self) -> Result<Program, AssemblyError> {
+        self.next_line()?.header("Constants")?;
+
+        let mut constants: Vec<ProgramObject> = Vec::new();
+        while let Some(mut line) = self.peek_line() {
+            if line.starts_with("Globals") {
+                break;
+            }
+            self.position += 1;
+
+            line.skip_whitespace();
+            let column = line.column();
+            let index = line.index()?;
+            if index as usize != constants.len() {
+                return Err(line.error_at(column, format!("expected constant #{}, found #{}",
+                                                         constants.len(), index)));
+            }
+            line.expect(":")?;
+            constants.push(self.constant(line)?);
+        }
+
+        self.next_line()?.header("Globals")?;
+
+        let mut globals: Vec<ConstantPoolIndex> = Vec::new();
+        while let Some(mut line) = self.peek_line() {
+            if line.starts_with("Entry") {
+                break;
+            }
+            self.position += 1;
+
+            line.skip_whitespace();
+            globals.push(ConstantPoolIndex::new(line.index()?));
+            line.end()?;
+        }
+
+        let mut line = self.next_line()?;
+        line.header_inline("Entry")?;
+        line.skip_whitespace();
+        let entry = ConstantPoolIndex::new(line.index()?);
+        line.end()?;
+
+        if let Some(line) = self.peek_line() {
+            return Err(line.error("unexpected input after entry point"));
+        }
+
+        let (code, constants) = share_nested_code(self.code, constants);
+        Ok(Program::new(Code::from(code), constants, globals, entry))
+    }
+
+    fn constant(&mut self, mut line: Line<'a>) -> Result<ProgramObject, AssemblyError> {
+        line.skip_whitespace();
+        let column = line.column();
+        let object = match line.word().as_str() {
+            "Null" => ProgramObject::Null,
+            "Int" => {
+                line.expect("(")?;
+                let value = line.number::<i32>()?;
+                line.expect(")")?;
+                ProgramObject::Integer(value)
+            }
+            "Bool" => {
+                line.expect("(")?;
+                let column = line.column();
+                let value = match line.word().as_str() {
+                    "true" => true,
+                    "false" => false,
+                    other => return Err(line.error_at(column, format!("expected boolean, found `{}`", other))),
+                };
+                line.expect(")")?;
+                ProgramObject::Boolean(value)
+            }
+            "String" => {
+                line.expect("(")?;
+                let value = line.string()?;
+                line.expect(")")?;
+                ProgramObject::String(value)
+            }
+            "Slot" => {
+                line.expect("(")?;
+                let name = ConstantPoolIndex::new(line.index()?);
+                line.expect(")")?;
+                ProgramObject::Slot { name }
+            }
+            "Class" => {
+                line.expect("(")?;
+                let mut members = Vec::new();
+                line.skip_whitespace();
+                if !line.starts_with(")") {
+                    loop {
+                        line.skip_whitespace();
+                        members.push(ConstantPoolIndex::new(line.index()?));
+                        line.skip_whitespace();
+                        if !line.starts_with(",") {
+                            break;
+                        }
+                        line.expect(",")?;
+                    }
+                }
+                line.expect(")")?;
+                ProgramObject::Class(members)
+            }
+            "Method" => {
+                line.expect("(")?;
+                let name = ConstantPoolIndex::new(line.index()?);
+                line.expect(",")?;
+                line.keyword("nargs")?;
+                line.expect(":")?;
+                let arguments = Arity::new(line.number::<u8>()?);
+                line.expect(",")?;
+                line.keyword("nlocals")?;
+                line.expect(":")?;
+                let locals = Size::new(line.number::<u16>()?);
+                line.expect(")")?;
+                line.expect(":")?;
+                line.end()?;
+
+                let start = self.code.len();
+                while let Some(line) = self.peek_line() {
+                    if line.starts_with("#") || line.starts_with("Globals") {
+                        break;
+                    }
+                    self.position += 1;
+                    let opcode = instruction(line)?;
+                    self.code.push(opcode);
+                }
+                let code = AddressRange::from(start, self.code.len() - start);
+
+                return Ok(ProgramObject::Method { name, arguments, locals, code });
+            }
+            "" => return Err(line.error_at(column, "expected constant".to_string())),
+            other => return Err(line.error_at(column, format!("unknown constant kind `{}`", other))),
+        };
+        line.end()?;
+        Ok(object)
+    }
+
+    fn peek_line(&self) -> Option<Line<'a>> {
+        self.lines.get(self.position).map(|(number, text)| Line::new(*number, text))
+    }
+
+    fn next_line(&mut self) -> Result<Line<'a>, AssemblyError> {
+        match self.peek_line() {
+            Some(line) => {
+                self.position += 1;
+                Ok(line)
+            }
+            None => {
+                let number = self.lines.last().map_or(1, |(number, _)| *number);
+                Err(AssemblyError { line: number, column: 1, message: "unexpected end of input".to_string() })
+            }
+        }
+    }
+}
+
+/// Lays out again the methods of a program assembled one body after another, placing each method
+/// whose body also appears between `goto L` and `label L` in a longer method at that copy.
+///
+/// Longer methods are placed first, so the copy a method is given is always in a method that
+/// has already been placed, and the copy in the shortest such method wins. Methods with the same
+/// body take the copies in constant pool order. Methods that are not nested in any other are
+/// laid out one after another in constant pool order, as before.
+fn share_nested_code(code: Vec<OpCode>, constants: Vec<ProgramObject>) -> (Vec<OpCode>, Vec<ProgramObject>) {
+    let ranges: Vec<(usize, usize, usize)> = constants.iter().enumerate()
+        .filter_map(|(index, object)| match object {
+            ProgramObject::Method { code, .. } => Some((index, code.start().value_usize(), code.length())),
+            _ => None,
+        })
+        .collect();
+    let mut order: Vec<&(usize, usize, usize)> = ranges.iter().collect();
+    order.sort_by(|(left, _, left_length), (right, _, right_length)| right_length.cmp(left_length).then(left.cmp(right)));
+
+    // Every method is placed at an offset into the code of a method that is not nested itself,
+    // its root. `placed` holds the method, where its own copy was assembled, its length and its
+    // placement, shortest first.
+    let mut placed: Vec<(usize, usize, usize, (usize, usize))> = Vec::new();
+    let mut claimed: HashSet<(usize, usize)> = HashSet::new();
+    let mut placements: HashMap<usize, (usize, usize)> = HashMap::new();
+    for &&(method, start, length) in order.iter() {
+        let body = &code[start..start + length];
+        let guarded = |window: &[OpCode]| match (&window[0], &window[length + 1]) {
+            (OpCode::Jump { label }, OpCode::Label { name }) => label == name && &window[1..=length] == body,
+            _ => false,
+        };
+        let copy = placed.iter()
+            .flat_map(|&(_, outer, outer_length, (root, offset))| {
+                code[outer..outer + outer_length].windows(length + 2).enumerate()
+                    .filter(|(_, window)| guarded(window))
+                    .map(move |(position, _)| (root, offset + position + 1))
+            })
+            .find(|copy| !claimed.contains(copy));
+
+        // An empty body would be found between any jump and the label right after it.
+        let placement = match copy.filter(|_| length > 0) {
+            Some(copy) => {
+                claimed.insert(copy);
+                copy
+            }
+            None => (method, 0),
+        };
+        placed.insert(0, (method, start, length, placement));
+        placements.insert(method, placement);
+    }
+
+    let mut laid_out: Vec<OpCode> = Vec::new();
+    let mut roots: HashMap<usize, usize> = HashMap::new();
+    for (method, start, length) in ranges.iter() {
+        if placements[method].0 == *method {
+            roots.insert(*method, laid_out.len());
+            laid_out.extend_from_slice(&code[*start..start + length]);
+        }
+    }
+
+    let constants = constants.into_iter().enumerate().map(|(index, object)| match object {
+        ProgramObject::Method { name, arguments, locals, code } => {
+            let (root, offset) = placements[&index];
+            ProgramObject::Method { name, arguments, locals, code: AddressRange::from(roots[&root] + offset, code.length()) }
+        }
+        object => object,
+    }).collect();
+    (laid_out, constants)
+}
+
+fn instruction(mut line: Line) -> Result<OpCode, AssemblyError> {
+    line.skip_whitespace();
+    let column = line.column();
+    let mnemonic = line.word();
+    let opcode = match mnemonic.as_str() {
+        "label" => OpCode::Label { name: line.operand_index()? },
+        "lit" => OpCode::Literal { index: line.operand_index()? },
+        "printf" => OpCode::Print { format: line.operand_index()?, arguments: line.operand_arity()? },
+        "array" => OpCode::Array,
+        "object" => OpCode::Object { class: line.operand_index()? },
+        "branch" => OpCode::Branch { label: line.operand_index()? },
+        "goto" => OpCode::Jump { label: line.operand_index()? },
+        "return" => OpCode::Return,
+        "drop" => OpCode::Drop,
+        "skip" => OpCode::Skip,
+        "call" => {
+            line.skip_whitespace();
+            if line.starts_with("slot") {
+                line.keyword("slot")?;
+                OpCode::CallMethod { name: line.operand_index()?, arguments: line.operand_arity()? }
+            } else {
+                OpCode::CallFunction { name: line.operand_index()?, arguments: line.operand_arity()? }
+            }
+        }
+        "get" | "set" => {
+            line.skip_whitespace();
+            let column = line.column();
+            let getter = mnemonic == "get";
+            match (getter, line.word().as_str()) {
+                (true, "local") => OpCode::GetLocal { index: line.operand_local()? },
+                (false, "local") => OpCode::SetLocal { index: line.operand_local()? },
+                (true, "global") => OpCode::GetGlobal { name: line.operand_index()? },
+                (false, "global") => OpCode::SetGlobal { name: line.operand_index()? },
+                (true, "slot") => OpCode::GetSlot { name: line.operand_index()? },
+                (false, "slot") => OpCode::SetSlot { name: line.operand_index()? },
+                (_, other) => return Err(line.error_at(column, format!("expected `local`, `global` or `slot` after `{}`, found `{}`",
+                                                                      mnemonic, other))),
+            }
+        }
+        "" => return Err(line.error_at(column, "expected instruction".to_string())),
+        other => return Err(line.error_at(column, format!("unknown instruction `{}`", other))),
+    };
+    line.end()?;
+    Ok(opcode)
+}
+
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+    offset: usize,
+}
+
+impl<'a> Line<'a> {
+    fn new(number: usize, text: &'a str) -> Self {
+        Line { number, text, offset: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.offset..]
+    }
+
+    fn column(&self) -> usize {
+        self.text[..self.offset].chars().count() + 1
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.rest().trim_start().starts_with(prefix)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.offset += rest.len() - rest.trim_start().len();
+    }
+
+    fn word(&mut self) -> String {
+        let rest = self.rest();
+        let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+        self.offset += length;
+        rest[..length].to_string()
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), AssemblyError> {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.offset += token.len();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", token)))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), AssemblyError> {
+        self.skip_whitespace();
+        let column = self.column();
+        let word = self.word();
+        if word == keyword {
+            Ok(())
+        } else {
+            Err(self.error_at(column, format!("expected `{}`, found `{}`", keyword, word)))
+        }
+    }
+
+    fn header(&mut self, name: &str) -> Result<(), AssemblyError> {
+        self.header_inline(name)?;
+        self.end()
+    }
+
+    fn header_inline(&mut self, name: &str) -> Result<(), AssemblyError> {
+        self.keyword(name)?;
+        self.expect(":")
+    }
+
+    fn end(&mut self) -> Result<(), AssemblyError> {
+        self.skip_whitespace();
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(self.error("unexpected trailing input"))
+        }
+    }
+
+    fn number<N: FromStr>(&mut self) -> Result<N, AssemblyError> {
+        self.skip_whitespace();
+        let column = self.column();
+        let rest = self.rest();
+        let sign = if rest.starts_with('-') { 1 } else { 0 };
+        let length = rest[sign..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - sign) + sign;
+        let digits = &rest[..length];
+        if length == sign {
+            return Err(self.error("expected number"));
+        }
+        self.offset += length;
+        digits.parse::<N>()
+            .map_err(|_| self.error_at(column, format!("number `{}` is out of range", digits)))
+    }
+
+    fn index(&mut self) -> Result<u16, AssemblyError> {
+        self.skip_whitespace();
+        if !self.rest().starts_with('#') {
+            return Err(self.error("expected constant pool index `#n`"));
+        }
+        self.offset += 1;
+        if self.rest().starts_with(char::is_whitespace) {
+            return Err(self.error("expected number"));
+        }
+        self.number::<u16>()
+    }
+
+    fn operand_index(&mut self) -> Result<ConstantPoolIndex, AssemblyError> {
+        self.index().map(ConstantPoolIndex::new)
+    }
+
+    fn operand_arity(&mut self) -> Result<Arity, AssemblyError> {
+        self.number::<u8>().map(Arity::new)
+    }
+
+    fn operand_local(&mut self) -> Result<LocalFrameIndex, AssemblyError> {
+        self.number::<u16>().map(LocalFrameIndex::new)
+    }
+
+    /// Reads a string literal in the escaped form `{:?}` produces.
+    fn string(&mut self) -> Result<String, AssemblyError> {
+        self.skip_whitespace();
+        if !self.rest().starts_with('"') {
+            return Err(self.error("expected string literal"));
+        }
+        let start = self.column();
+        self.offset += 1;
+
+        let mut value = String::new();
+        loop {
+            let column = self.column();
+            let mut chars = self.rest().chars();
+            let character = match chars.next() {
+                Some(character) => character,
+                None => return Err(self.error_at(start, "unterminated string literal".to_string())),
+            };
+            self.offset += character.len_utf8();
+            match character {
+                '"' => return Ok(value),
+                '\\' => {
+                    let escape = match chars.next() {
+                        Some(escape) => escape,
+                        None => return Err(self.error_at(start, "unterminated string literal".to_string())),
+                    };
+                    self.offset += escape.len_utf8();
+                    match escape {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        '0' => value.push('\0'),
+                        '\\' => value.push('\\'),
+                        '"' => value.push('"'),
+                        '\'' => value.push('\''),
+                        'u' => value.push(self.unicode_escape(column)?),
+                        other => return Err(self.error_at(column, format!("unknown escape `\\{}`", other))),
+                    }
+                }
+                other => value.push(other),
+            }
+        }
+    }
+
+    fn unicode_escape(&mut self, column: usize) -> Result<char, AssemblyError> {
+        let rest = self.rest();
+        let end = match rest.find('}') {
+            Some(end) if rest.starts_with('{') => end,
+            _ => return Err(self.error_at(column, "malformed unicode escape".to_string())),
+        };
+        let digits = &rest[1..end];
+        self.offset += end + 1;
+        u32::from_str_radix(digits, 16).ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(|| self.error_at(column, format!("invalid unicode escape `\\u{{{}}}`", digits)))
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> AssemblyError {
+        self.error_at(self.column(), message.into())
+    }
+
+    fn error_at(&self, column: usize, message: String) -> AssemblyError {
+        AssemblyError { line: self.number, column, message }
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+
+use fml_ast::AST;
+
+use crate::container;
+use crate::debug::PrettyPrint;
+use crate::debugger;
+use crate::diagnostics;
+use crate::interpreter::State;
+use crate::limits::{self, Limits};
+use crate::optimizer::{self, Optimizations};
+use crate::profiler;
+use crate::program::Program;
+use crate::verifier;
+
+const USAGE: &str = "\
+usage: simulate <command> [options] <file>
+
+commands:
+    run <file.fml>                   compile and run an FML program
+    compile <file.fml> -o <file.bc>  compile an FML program to bytecode
+    execute <file.bc>                run a bytecode program
+    disassemble <file.bc>            print a bytecode program as a listing
+    debug <file.bc>                  step through a bytecode program, reading commands from stdin
+
+options:
+    -o <file>      where to write the bytecode produced by compile
+    --verbose      print the compiled program and what the optimizer did
+    --dump-ast     print the syntax tree after parsing
+    --no-optimize  keep the bytecode exactly as the compiler emitted it
+    --profile      print instruction counts after running
+
+limits, for run and execute:
+    --max-instructions <n>  stop after executing n instructions
+    --max-objects <n>       stop once more than n objects are allocated
+    --max-elements <n>      stop before arrays hold more than n elements in total
+    --max-depth <n>         stop once more than n call frames are active
+    --max-output <n>        stop once the program prints more than n bytes
+
+Use - as the file to read from standard input.";
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Failure {
+    Usage(String),
+    Io(String),
+    Parse(String),
+    Compile(String),
+    Bytecode(String),
+    Runtime(String),
+    Limit(String),
+    /// A panic that none of the other failures account for.
+    Internal(String),
+}
+
+impl Failure {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::Usage(_) => 2,
+            Failure::Io(_) => 3,
+            Failure::Parse(_) => 4,
+            Failure::Compile(_) => 5,
+            Failure::Bytecode(_) => 6,
+            Failure::Runtime(_) => 7,
+            Failure::Limit(_) => 8,
+            Failure::Internal(_) => 9,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            Failure::Io(message) => write!(f, "{}", message),
+            Failure::Parse(message) => write!(f, "parse error: {}", message),
+            Failure::Compile(message) => write!(f, "{}", message),
+            Failure::Bytecode(message) => write!(f, "invalid bytecode: {}", message),
+            Failure::Runtime(message) => write!(f, "runtime error: {}", message),
+            Failure::Limit(message) => write!(f, "stopped: {}", message),
+            Failure::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Self {
+        Failure::Io(error.to_string())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Options {
+    pub verbose: bool,
+    pub dump_ast: bool,
+    pub no_optimize: bool,
+    pub profile: bool,
+    pub output: Option<String>,
+    pub limits: Limits,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Command {
+    pub name: String,
+    pub input: String,
+    pub options: Options,
+}
+
+/// Runs the command described by `arguments` (without the program name) and returns the exit
+/// code for the process.
+pub fn main<W: Write, E: Write>(arguments: &[String], out: &mut W, err: &mut E) -> i32 {
+    let result = match catch(|| run(arguments, out, err)) {
+        Ok(result) => result,
+        Err(message) => Err(Failure::Internal(message)),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(failure) => {
+            let _ = writeln!(err, "{}", failure);
+            failure.exit_code()
+        }
+    }
+}
+
+pub fn run<W: Write, E: Write>(arguments: &[String], out: &mut W, err: &mut E) -> Result<(), Failure> {
+    let command = parse_arguments(arguments)?;
+    match command.name.as_str() {
+        "run" => {
+            let program = compile(&command, err)?;
+            evaluate(&program, &command.options, out, err)
+        }
+        "compile" => {
+            let program = compile(&command, err)?;
+            let flags = if command.options.no_optimize { 0 } else { container::OPTIMIZED };
+            let bytes = container::write(&program, flags);
+            let path = command.options.output.as_ref().unwrap();
+            fs::write(path, bytes).map_err(|error| Failure::Io(format!("cannot write {}: {}", path, error)))
+        }
+        "execute" => {
+            let program = load(&command.input)?;
+            evaluate(&program, &command.options, out, err)
+        }
+        "disassemble" => {
+            let program = load(&command.input)?;
+            let mut listing: Vec<u8> = Vec::new();
+            program.pretty_print(&mut listing);
+            out.write_all(&listing)?;
+            writeln!(out)?;
+            Ok(())
+        }
+        "debug" => {
+            let program = load(&command.input)?;
+            let stdin = io::stdin();
+            match catch(|| debugger::session(&program, stdin.lock(), out)) {
+                Ok(result) => Ok(result?),
+                Err(message) => Err(Failure::Runtime(message)),
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+pub fn parse_arguments(arguments: &[String]) -> Result<Command, Failure> {
+    let mut options = Options::default();
+    let mut positional: Vec<&String> = Vec::new();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--verbose" => options.verbose = true,
+            "--dump-ast" => options.dump_ast = true,
+            "--no-optimize" => options.no_optimize = true,
+            "--profile" => options.profile = true,
+            "--max-instructions" => options.limits.instructions = Some(number(argument, arguments.next())?),
+            "--max-objects" => options.limits.objects = Some(number(argument, arguments.next())?),
+            "--max-elements" => options.limits.elements = Some(number(argument, arguments.next())?),
+            "--max-depth" => options.limits.frames = Some(number(argument, arguments.next())?),
+            "--max-output" => options.limits.output = Some(number(argument, arguments.next())?),
+            "-o" => match arguments.next() {
+                Some(output) => options.output = Some(output.clone()),
+                None => return Err(Failure::Usage("-o needs a file name".to_string())),
+            },
+            "-" => positional.push(argument),
+            flag if flag.starts_with('-') =>
+                return Err(Failure::Usage(format!("unknown option `{}`", flag))),
+            _ => positional.push(argument),
+        }
+    }
+
+    let (name, input) = match positional.as_slice() {
+        [] => return Err(Failure::Usage("no command given".to_string())),
+        [name] => return Err(Failure::Usage(format!("`{}` needs a file", name))),
+        [name, input] => (name.to_string(), input.to_string()),
+        [_, _, extra, ..] => return Err(Failure::Usage(format!("unexpected argument `{}`", extra))),
+    };
+    match (name.as_str(), &options.output) {
+        ("compile", None) => return Err(Failure::Usage("compile needs an output file given with -o".to_string())),
+        ("compile", Some(_)) => {}
+        ("run", None) | ("execute", None) | ("disassemble", None) | ("debug", None) => {}
+        ("run", Some(_)) | ("execute", Some(_)) | ("disassemble", Some(_)) | ("debug", Some(_)) =>
+            return Err(Failure::Usage(format!("{} does not write an output file", name))),
+        _ => return Err(Failure::Usage(format!("unknown command `{}`", name))),
+    }
+    if options.limits != Limits::none() && !matches!(name.as_str(), "run" | "execute") {
+        return Err(Failure::Usage(format!("{} does not run the program, so it takes no limits", name)));
+    }
+    Ok(Command { name, input, options })
+}
+
+fn number<N: FromStr>(flag: &str, value: Option<&String>) -> Result<N, Failure> {
+    value.and_then(|value| value.parse().ok())
+        .ok_or_else(|| Failure::Usage(format!("{} needs a number", flag)))
+}
+
+fn read(path: &str) -> Result<Vec<u8>, Failure> {
+    let mut bytes = Vec::new();
+    let result = match path {
+        "-" => io::stdin().read_to_end(&mut bytes).map(|_| ()),
+        path => fs::File::open(path).and_then(|mut file| file.read_to_end(&mut bytes)).map(|_| ()),
+    };
+    result.map_err(|error| Failure::Io(format!("cannot read {}: {}", path, error)))?;
+    Ok(bytes)
+}
+
+/// Parses, checks, compiles and optimizes an FML source file.
+fn compile<E: Write>(command: &Command, err: &mut E) -> Result<Program, Failure> {
+    let source = String::from_utf8(read(&command.input)?)
+        .map_err(|error| Failure::Io(format!("cannot read {}: {}", command.input, error)))?;
+
+    let ast: AST = fml_parser::parse(&source).map_err(|error| Failure::Parse(format!("{:?}", error)))?;
+    if command.options.dump_ast {
+        writeln!(err, "{:#?}", ast)?;
+    }
+
+    let (program, warnings) = match catch(|| diagnostics::compile(&ast)) {
+        Ok(Ok(compiled)) => compiled,
+        Ok(Err(diagnostics)) => return Err(Failure::Compile(diagnostics.to_string().trim_end().to_string())),
+        Err(message) => return Err(Failure::Compile(format!("compiler error: {}", message))),
+    };
+    for warning in warnings {
+        writeln!(err, "warning: {}", warning)?;
+    }
+
+    let optimizations = if command.options.no_optimize { Optimizations::none() } else { Optimizations::all() };
+    let (program, report) = optimizer::optimize_with_report(&program, &optimizations);
+    if command.options.verbose {
+        writeln!(err, "{}", report)?;
+        let mut listing: Vec<u8> = Vec::new();
+        program.pretty_print(&mut listing);
+        err.write_all(&listing)?;
+        writeln!(err)?;
+    }
+    Ok(program)
+}
+
+/// Reads a bytecode file, with or without a container header, and verifies it, so that
+/// malformed programs are reported as such rather than as failures at run time.
+fn load(path: &str) -> Result<Program, Failure> {
+    let bytes = read(path)?;
+    let (program, _) = match catch(|| container::read(&bytes)) {
+        Ok(Ok(loaded)) => loaded,
+        Ok(Err(error)) => return Err(Failure::Bytecode(error.to_string())),
+        Err(message) => return Err(Failure::Bytecode(message)),
+    };
+    verifier::verify(&program).map_err(|diagnostics| {
+        let messages: Vec<String> = diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect();
+        Failure::Bytecode(messages.join("\n"))
+    })?;
+    Ok(program)
+}
+
+/// Runs the program to completion or until it exceeds its limits, writing what it prints to
+/// `out` as it goes, so that output before a failure is kept.
+fn evaluate<W: Write, E: Write>(program: &Program, options: &Options, out: &mut W, err: &mut E) -> Result<(), Failure> {
+    let mut written = Ok(());
+    let mut flush = |printed: &str| {
+        if written.is_ok() {
+            written = out.write_all(printed.as_bytes()).and_then(|_| out.flush());
+        }
+    };
+    let result = catch(|| {
+        let mut state = State::from(program);
+        if options.profile {
+            let (profile, result) = profiler::profile_with_limits(program, &mut state, &options.limits, &mut flush);
+            (Some(profile), result)
+        } else {
+            (None, limits::run_flushing(program, &mut state, &options.limits, &mut flush))
+        }
+    });
+
+    written?;
+    match result {
+        Ok((profile, result)) => {
+            if let Some(profile) = profile {
+                profile.table(err)?;
+            }
+            result.map_err(|exceeded| Failure::Limit(exceeded.to_string()))
+        }
+        Err(message) => Err(Failure::Runtime(message)),
+    }
+}
+
+/// Calls `f`, turning a panic into its message. The panic hook is left alone, so the default
+/// one still prints where the panic happened.
+fn catch<T, F: FnOnce() -> T>(f: F) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown error".to_string(),
+        },
+    })
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::io::Cursor;
+
+use crate::program::Program;
+use crate::serializable::Serializable;
+
+/// Starts every container. A headerless program can never begin with these bytes: they would
+/// read as a constant pool of 0x4D46 entries whose first tag, 0x4C, is not a valid object tag.
+pub const MAGIC: [u8; 4] = *b"FMLB";
+pub const VERSION: u16 = 1;
+
+/// Set when the program was run through the optimizer.
+pub const OPTIMIZED: u16 = 0x0001;
+const KNOWN_FLAGS: u16 = OPTIMIZED;
+
+/// Magic number, version, flags, payload length and checksum.
+const HEADER_SIZE: usize = 4 + 2 + 2 + 4 + 4;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Header {
+    pub version: u16,
+    pub flags: u16,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ContainerError {
+    Truncated { expected: usize, found: usize },
+    UnsupportedVersion { version: u16 },
+    UnknownFlags { flags: u16 },
+    TrailingBytes { expected: usize, found: usize },
+    ChecksumMismatch { expected: u32, found: u32 },
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContainerError::Truncated { expected, found } =>
+                write!(f, "container is truncated: expected {} bytes but found {}", expected, found),
+            ContainerError::UnsupportedVersion { version } =>
+                write!(f, "container version {} is not supported (expected {})", version, VERSION),
+            ContainerError::UnknownFlags { flags } =>
+                write!(f, "container has unknown flags {:#06x}", flags),
+            ContainerError::TrailingBytes { expected, found } =>
+                write!(f, "container should end after {} bytes but has {}", expected, found),
+            ContainerError::ChecksumMismatch { expected, found } =>
+                write!(f, "container checksum is {:#010x} but the payload sums to {:#010x}", expected, found),
+        }
+    }
+}
+
+impl std::error::Error for ContainerError {}
+
+/// Serializes `program` behind a container header.
+pub fn write(program: &Program, flags: u16) -> Vec<u8> {
+    let mut payload: Vec<u8> = Vec::new();
+    program.serialize(&mut payload);
+
+    let mut bytes: Vec<u8> = Vec::with_capacity(HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&flags.to_le_bytes());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// Deserializes a program written by `write`, checking its header first. Input that does not
+/// start with `MAGIC` is read as a headerless program, as written by `Program::serialize`, and
+/// comes back without a header.
+pub fn read(bytes: &[u8]) -> Result<(Program, Option<Header>), ContainerError> {
+    if !bytes.starts_with(&MAGIC) {
+        return Ok((Program::from_bytes(&mut Cursor::new(bytes.to_vec())), None));
+    }
+    if bytes.len() < HEADER_SIZE {
+        return Err(ContainerError::Truncated { expected: HEADER_SIZE, found: bytes.len() });
+    }
+
+    let u16_at = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+    let u32_at = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+    let version = u16_at(4);
+    let flags = u16_at(6);
+    let length = u32_at(8) as usize;
+    let checksum = u32_at(12);
+
+    if version != VERSION {
+        return Err(ContainerError::UnsupportedVersion { version });
+    }
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(ContainerError::UnknownFlags { flags: flags & !KNOWN_FLAGS });
+    }
+    let expected = HEADER_SIZE + length;
+    if bytes.len() < expected {
+        return Err(ContainerError::Truncated { expected, found: bytes.len() });
+    }
+    if bytes.len() > expected {
+        return Err(ContainerError::TrailingBytes { expected, found: bytes.len() });
+    }
+    let payload = &bytes[HEADER_SIZE..];
+    let found = crc32(payload);
+    if found != checksum {
+        return Err(ContainerError::ChecksumMismatch { expected: checksum, found });
+    }
+
+    Ok((Program::from_bytes(&mut Cursor::new(payload.to_vec())), Some(Header { version, flags })))
+}
+
+/// CRC-32 as used by zlib and PNG (reflected polynomial 0xEDB88320).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xFFFF_FFFF;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::bytecode::OpCode;
+use crate::debug::PrettyPrint;
+use crate::inspect;
+use crate::interpreter::{interpret, State};
+use crate::methods::Methods;
+use crate::objects::{Pointer, ProgramObject};
+use crate::program::Program;
+use crate::types::Address;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Breakpoint {
+    Address(usize),
+    Label(String),
+    Method(String),
+}
+
+/// Drives the interpreter one instruction at a time on behalf of a user typing commands.
+pub struct Debugger<'a> {
+    program: &'a Program,
+    state: State,
+    output: String,
+    printed: usize,
+    methods: Methods,
+    labels: HashMap<String, usize>,
+    breakpoints: Vec<(Breakpoint, usize)>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let constants = program.constants();
+        let labels = program.code().as_vec().iter().enumerate()
+            .filter_map(|(address, opcode)| match opcode {
+                OpCode::Label { name } => match constants.get(name.value() as usize) {
+                    Some(ProgramObject::String(name)) => Some((name.clone(), address)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+
+        Debugger {
+            program,
+            state: State::from(program),
+            output: String::new(),
+            printed: 0,
+            methods: Methods::from(program),
+            labels,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    fn address(&self) -> Option<usize> {
+        self.state.instruction_pointer.as_ref().map(|address| address.value_usize())
+    }
+
+    fn finished(&self) -> bool {
+        self.state.instruction_pointer.is_none()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        match self.address() {
+            Some(address) => self.breakpoints.iter().any(|(_, at)| *at == address),
+            None => false,
+        }
+    }
+
+    fn step(&mut self) {
+        if !self.finished() {
+            interpret(&mut self.state, &mut self.output, self.program);
+        }
+    }
+
+    /// Keeps stepping until `done` holds, a breakpoint is hit or the program ends. The current
+    /// instruction is always executed, so resuming from a breakpoint makes progress.
+    fn run_until<F: Fn(&State) -> bool>(&mut self, done: F) {
+        self.step();
+        while !self.finished() && !self.at_breakpoint() && !done(&self.state) {
+            self.step();
+        }
+    }
+
+    fn resolve(&self, breakpoint: &Breakpoint) -> Result<usize, String> {
+        match breakpoint {
+            Breakpoint::Address(address) if *address < self.program.code().as_vec().len() => Ok(*address),
+            Breakpoint::Address(address) => Err(format!("address {} is outside of the code", address)),
+            Breakpoint::Label(name) => self.labels.get(name).cloned()
+                .ok_or_else(|| format!("no label named `{}`", name)),
+            Breakpoint::Method(name) => self.methods.named(name).map(|method| method.start)
+                .ok_or_else(|| format!("no method named `{}`", name)),
+        }
+    }
+
+    /// Executes a single command line, writing its results to `sink`. Returns `false` once
+    /// the user asks to quit.
+    pub fn execute<W: Write>(&mut self, line: &str, sink: &mut W) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            [comment, ..] if comment.starts_with('#') => {}
+            ["break", rest @ ..] | ["delete", rest @ ..] => {
+                let breakpoint = match rest {
+                    [address] => address.parse::<usize>().ok().map(Breakpoint::Address),
+                    ["label", name] => Some(Breakpoint::Label(name.to_string())),
+                    ["method", name] => Some(Breakpoint::Method(name.to_string())),
+                    _ => None,
+                };
+                match (words[0], breakpoint) {
+                    (_, None) => writeln!(sink, "usage: {} <address> | label <name> | method <name>", words[0])?,
+                    ("break", Some(breakpoint)) => match self.resolve(&breakpoint) {
+                        Ok(address) => {
+                            writeln!(sink, "breakpoint at {}", address)?;
+                            self.breakpoints.push((breakpoint, address));
+                        }
+                        Err(message) => writeln!(sink, "{}", message)?,
+                    },
+                    (_, Some(breakpoint)) => {
+                        let before = self.breakpoints.len();
+                        self.breakpoints.retain(|(existing, _)| *existing != breakpoint);
+                        if self.breakpoints.len() == before {
+                            writeln!(sink, "no such breakpoint")?;
+                        }
+                    }
+                }
+            }
+            ["step"] => {
+                self.step();
+                self.report(sink)?;
+            }
+            ["next"] => {
+                let depth = self.state.frames.len();
+                self.run_until(|state| state.frames.len() <= depth);
+                self.report(sink)?;
+            }
+            ["finish"] => {
+                let depth = self.state.frames.len();
+                self.run_until(|state| state.frames.len() < depth);
+                self.report(sink)?;
+            }
+            ["continue"] => {
+                self.run_until(|_| false);
+                self.report(sink)?;
+            }
+            ["where"] => self.location(sink)?,
+            ["stack"] => {
+                for pointer in self.state.operands.iter().rev() {
+                    self.value(pointer, sink)?;
+                }
+            }
+            ["locals"] => {
+                if let Some(frame) = self.state.frames.last() {
+                    for (index, pointer) in inspect::locals(frame).iter().enumerate() {
+                        write!(sink, "{}: ", index)?;
+                        self.value(pointer, sink)?;
+                    }
+                }
+            }
+            ["globals"] => {
+                let mut globals: Vec<(&String, &Pointer)> = self.state.globals.iter().collect();
+                globals.sort_by_key(|(name, _)| *name);
+                for (name, pointer) in globals {
+                    write!(sink, "{}: ", name)?;
+                    self.value(pointer, sink)?;
+                }
+            }
+            ["heap", rest @ ..] => match rest {
+                [index] if index.parse::<usize>().is_ok() =>
+                    self.value(&Pointer::from(index.parse::<usize>().unwrap()), sink)?,
+                _ => writeln!(sink, "usage: heap <pointer>")?,
+            },
+            ["quit"] => return Ok(false),
+            _ => writeln!(sink, "unknown command `{}`", line.trim())?,
+        }
+        Ok(true)
+    }
+
+    fn value<W: Write>(&self, pointer: &Pointer, sink: &mut W) -> io::Result<()> {
+        match inspect::object(&self.state.memory, pointer) {
+            Some(object) => writeln!(sink, "{:?} = {:?}", pointer, object),
+            None => writeln!(sink, "{:?} is not allocated", pointer),
+        }
+    }
+
+    /// Flushes whatever the program printed since the last command and shows where it stopped.
+    fn report<W: Write>(&mut self, sink: &mut W) -> io::Result<()> {
+        write!(sink, "{}", &self.output[self.printed..])?;
+        self.printed = self.output.len();
+        if self.at_breakpoint() {
+            write!(sink, "breakpoint: ")?;
+        }
+        self.location(sink)
+    }
+
+    fn location<W: Write>(&self, sink: &mut W) -> io::Result<()> {
+        let address = match self.address() {
+            Some(address) => address,
+            None => return writeln!(sink, "program finished"),
+        };
+        if let Some(method) = self.methods.at(address) {
+            write!(sink, "{} ", method.name)?;
+        }
+        write!(sink, "{}: ", address)?;
+        match self.program.get_opcode(&Address::from_usize(address)) {
+            Some(opcode) => opcode.pretty_print(sink),
+            None => write!(sink, "?")?,
+        }
+        writeln!(sink)
+    }
+}
+
+/// Runs debugger commands from `input`, one per line, until it runs out or reads `quit`.
+/// Commands are echoed to `sink` so that a session read from a file can be compared against
+/// a recorded transcript.
+pub fn session<R: BufRead, W: Write>(program: &Program, input: R, sink: &mut W) -> io::Result<()> {
+    let mut debugger = Debugger::new(program);
+    debugger.location(sink)?;
+    for line in input.lines() {
+        let line = line?;
+        writeln!(sink, "(fml) {}", line)?;
+        if !debugger.execute(&line, sink)? {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use fml_ast::{AST, Identifier};
+
+use crate::compiler;
+use crate::program::Program;
+
+/// Checks the AST for mistakes the compiler would otherwise turn into bytecode that fails at
+/// run time, then compiles it. Warnings do not prevent compilation and are returned alongside
+/// the program.
+pub fn compile(ast: &AST) -> Result<(Program, Vec<Diagnostic>), Diagnostics> {
+    let diagnostics = check(ast);
+    if diagnostics.errors.is_empty() {
+        Ok((compiler::compile(ast), diagnostics.warnings))
+    } else {
+        Err(diagnostics)
+    }
+}
+
+pub fn check(ast: &AST) -> Diagnostics {
+    let mut checker = Checker::new(ast);
+    checker.check(ast);
+    Diagnostics { errors: checker.errors, warnings: checker.warnings }
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Diagnostics {
+    pub errors: Vec<Diagnostic>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    /// Name of the function or method containing the problem, if it is inside one.
+    pub function: Option<String>,
+    pub problem: Problem,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Problem {
+    UndefinedVariable { name: String },
+    UnknownFunction { name: String },
+    WrongArity { function: String, expected: usize, found: usize },
+    DuplicateParameter { name: String },
+    DuplicateMember { name: String },
+    UnusedLocal { name: String },
+    Shadowing { name: String },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(function) = &self.function {
+            write!(f, "in function `{}`: ", function)?;
+        }
+        match &self.problem {
+            Problem::UndefinedVariable { name } =>
+                write!(f, "variable `{}` is not defined", name),
+            Problem::UnknownFunction { name } =>
+                write!(f, "function `{}` is not defined", name),
+            Problem::WrongArity { function, expected, found } =>
+                write!(f, "function `{}` takes {} arguments but {} were given", function, expected, found),
+            Problem::DuplicateParameter { name } =>
+                write!(f, "parameter `{}` is declared more than once", name),
+            Problem::DuplicateMember { name } =>
+                write!(f, "object member `{}` is defined more than once", name),
+            Problem::UnusedLocal { name } =>
+                write!(f, "local variable `{}` is never read", name),
+            Problem::Shadowing { name } =>
+                write!(f, "local variable `{}` shadows an earlier definition", name),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for error in self.errors.iter() {
+            writeln!(f, "error: {}", error)?;
+        }
+        for warning in self.warnings.iter() {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+struct Local {
+    name: String,
+    parameter: bool,
+    read: bool,
+}
+
+/// The locals of one function or method, innermost block last.
+struct Frame {
+    function: String,
+    scopes: Vec<Vec<Local>>,
+}
+
+struct Checker {
+    /// Arity of every function defined outside of an object, wherever it is defined. Functions
+    /// are registered as globals before the program runs, so they can be called before their
+    /// definition.
+    functions: HashMap<String, usize>,
+    /// Every variable defined outside of a function body. Whether it is defined by the time it
+    /// is read depends on the order of execution, so this is deliberately permissive.
+    globals: HashSet<String>,
+    frames: Vec<Frame>,
+    errors: Vec<Diagnostic>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn new(ast: &AST) -> Self {
+        let mut checker = Checker {
+            functions: HashMap::new(),
+            globals: HashSet::new(),
+            frames: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        };
+        checker.declarations(ast, true);
+        checker
+    }
+
+    /// Collects function and global variable definitions ahead of checking.
+    fn declarations(&mut self, ast: &AST, top_level: bool) {
+        match ast {
+            AST::VariableDefinition { name, value } => {
+                if top_level {
+                    self.globals.insert(name.0.clone());
+                }
+                self.declarations(value, top_level);
+            }
+            AST::FunctionDefinition { function, parameters, body } => {
+                self.functions.insert(function.0.clone(), parameters.len());
+                self.declarations(body, false);
+            }
+            AST::ObjectDefinition { extends, members } => {
+                if let Some(extends) = extends {
+                    self.declarations(extends, top_level);
+                }
+                for member in members.iter() {
+                    match member.as_ref() {
+                        AST::VariableDefinition { value, .. } => self.declarations(value, top_level),
+                        AST::FunctionDefinition { body, .. } | AST::OperatorDefinition { body, .. } =>
+                            self.declarations(body, false),
+                        member => self.declarations(member, top_level),
+                    }
+                }
+            }
+            AST::OperatorDefinition { body, .. } => self.declarations(body, false),
+            ast => {
+                for child in children(ast) {
+                    self.declarations(child, top_level);
+                }
+            }
+        }
+    }
+
+    fn function(&self) -> Option<String> {
+        self.frames.last().map(|frame| frame.function.clone())
+    }
+
+    fn error(&mut self, problem: Problem) {
+        let function = self.function();
+        self.errors.push(Diagnostic { function, problem });
+    }
+
+    fn warning(&mut self, problem: Problem) {
+        let function = self.function();
+        self.warnings.push(Diagnostic { function, problem });
+    }
+
+    fn enter_function(&mut self, function: String, parameters: &[Identifier], this: bool) {
+        let mut scope: Vec<Local> = Vec::new();
+        if this {
+            scope.push(Local { name: "this".to_string(), parameter: true, read: false });
+        }
+        self.frames.push(Frame { function, scopes: Vec::new() });
+        for parameter in parameters.iter() {
+            if scope.iter().any(|local| local.name == parameter.0) {
+                self.error(Problem::DuplicateParameter { name: parameter.0.clone() });
+            } else {
+                scope.push(Local { name: parameter.0.clone(), parameter: true, read: false });
+            }
+        }
+        self.frames.last_mut().unwrap().scopes.push(scope);
+    }
+
+    fn leave_function(&mut self) {
+        self.leave_scope();
+        self.frames.pop();
+    }
+
+    fn enter_scope(&mut self) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.scopes.push(Vec::new());
+        }
+    }
+
+    fn leave_scope(&mut self) {
+        let scope = match self.frames.last_mut() {
+            Some(frame) => frame.scopes.pop().unwrap_or_default(),
+            None => return,
+        };
+        for local in scope.into_iter().filter(|local| !local.parameter && !local.read) {
+            self.warning(Problem::UnusedLocal { name: local.name });
+        }
+    }
+
+    fn define(&mut self, name: &Identifier) {
+        let shadows = match self.frames.last() {
+            Some(frame) => frame.scopes.iter().flatten().any(|local| local.name == name.0),
+            None => return,
+        };
+        if shadows {
+            self.warning(Problem::Shadowing { name: name.0.clone() });
+        }
+        if let Some(scope) = self.frames.last_mut().and_then(|frame| frame.scopes.last_mut()) {
+            scope.push(Local { name: name.0.clone(), parameter: false, read: false });
+        }
+    }
+
+    fn variable(&mut self, name: &Identifier, read: bool) {
+        let local = self.frames.last_mut().and_then(|frame| {
+            frame.scopes.iter_mut().rev().flat_map(|scope| scope.iter_mut().rev())
+                .find(|local| local.name == name.0)
+        });
+        match local {
+            Some(local) => local.read |= read,
+            None if self.globals.contains(&name.0) => {}
+            None => self.error(Problem::UndefinedVariable { name: name.0.clone() }),
+        }
+    }
+
+    fn check(&mut self, ast: &AST) {
+        match ast {
+            AST::VariableDefinition { name, value } => {
+                self.check(value);
+                self.define(name);
+            }
+            AST::VariableAccess { name } => self.variable(name, true),
+            AST::VariableMutation { name, value } => {
+                self.check(value);
+                self.variable(name, false);
+            }
+            AST::FunctionDefinition { function, parameters, body } => {
+                self.enter_function(function.0.clone(), parameters, false);
+                self.check(body);
+                self.leave_function();
+            }
+            AST::OperatorDefinition { operator, parameters, body } => {
+                self.enter_function(format!("{:?}", operator), parameters, true);
+                self.check(body);
+                self.leave_function();
+            }
+            AST::FunctionCall { function, arguments } => {
+                match self.functions.get(&function.0) {
+                    None => self.error(Problem::UnknownFunction { name: function.0.clone() }),
+                    Some(expected) if *expected != arguments.len() => {
+                        let expected = *expected;
+                        self.error(Problem::WrongArity {
+                            function: function.0.clone(), expected, found: arguments.len()
+                        })
+                    }
+                    Some(_) => {}
+                }
+                for argument in arguments.iter() {
+                    self.check(argument);
+                }
+            }
+            AST::ObjectDefinition { extends, members } => {
+                if let Some(extends) = extends {
+                    self.check(extends);
+                }
+                let mut names: HashSet<String> = HashSet::new();
+                for member in members.iter() {
+                    let name = match member.as_ref() {
+                        AST::VariableDefinition { name, value } => {
+                            self.check(value);
+                            name.0.clone()
+                        }
+                        AST::FunctionDefinition { function, parameters, body } => {
+                            self.enter_function(function.0.clone(), parameters, true);
+                            self.check(body);
+                            self.leave_function();
+                            function.0.clone()
+                        }
+                        AST::OperatorDefinition { operator, .. } => {
+                            self.check(member);
+                            format!("{:?}", operator)
+                        }
+                        member => {
+                            self.check(member);
+                            continue;
+                        }
+                    };
+                    if !names.insert(name.clone()) {
+                        self.error(Problem::DuplicateMember { name });
+                    }
+                }
+            }
+            AST::Block(children) => {
+                self.enter_scope();
+                for child in children.iter() {
+                    self.check(child);
+                }
+                self.leave_scope();
+            }
+            ast => {
+                for child in children(ast) {
+                    self.check(child);
+                }
+            }
+        }
+    }
+}
+
+/// The direct subexpressions of `ast`, in evaluation order.
+fn children(ast: &AST) -> Vec<&AST> {
+    match ast {
+        AST::Number(_) | AST::Boolean(_) | AST::Unit | AST::VariableAccess { .. } => vec!(),
+        AST::VariableDefinition { value, .. } | AST::VariableMutation { value, .. } => vec!(value),
+        AST::ArrayDefinition { size, value } => vec!(size, value),
+        AST::ObjectDefinition { extends, members } =>
+            extends.iter().chain(members.iter()).map(|child| child.as_ref()).collect(),
+        AST::ArrayAccess { array, index } => vec!(array, index),
+        AST::FieldAccess { object, .. } => vec!(object),
+        AST::ArrayMutation { array, index, value } => vec!(array, index, value),
+        AST::FieldMutation { object, value, .. } => vec!(object, value),
+        AST::FunctionDefinition { body, .. } | AST::OperatorDefinition { body, .. } => vec!(body),
+        AST::FunctionCall { arguments, .. } | AST::Print { arguments, .. } | AST::Block(arguments) =>
+            arguments.iter().map(|child| child.as_ref()).collect(),
+        AST::MethodCall { object, arguments, .. } | AST::OperatorCall { object, arguments, .. } =>
+            std::iter::once(object).chain(arguments.iter()).map(|child| child.as_ref()).collect(),
+        AST::Operation { left, right, .. } => vec!(left, right),
+        AST::Loop { condition, body } => vec!(condition, body),
+        AST::Conditional { condition, consequent, alternative } => vec!(condition, consequent, alternative),
+    }
+}
@@ -0,0 +1,41 @@
+use crate::interpreter::{LocalFrame, Memory};
+use crate::objects::{Object, Pointer};
+
+// Read-only access to interpreter state for the debugger and the execution limits. The
+// interpreter tests only construct `Memory` and `LocalFrame` and compare them whole, so the
+// accessors used here are assumptions about interpreter.rs, kept in this one place.
+
+/// The object `pointer` refers to, or `None` if nothing is allocated there.
+///
+/// Assumes `Memory::dereference`, returning `None` for any pointer past the last allocated
+/// object the way `Vec::get` does for the vector `Memory::from` wraps.
+pub fn object<'a>(memory: &'a Memory, pointer: &Pointer) -> Option<&'a Object> {
+    memory.dereference(pointer)
+}
+
+/// The number of objects allocated so far, given that at least `known` were. Pointers are
+/// handed out in order from 0, so this probes only past `known`.
+pub fn allocated(memory: &Memory, known: usize) -> usize {
+    let mut allocated = known;
+    while object(memory, &Pointer::from(allocated)).is_some() {
+        allocated += 1;
+    }
+    allocated
+}
+
+/// The value of the integer `pointer` refers to, or `None` if it refers to anything else.
+///
+/// Assumes `Object::Integer(i32)`, the variant `Object::from_i32` builds.
+pub fn integer(memory: &Memory, pointer: &Pointer) -> Option<i32> {
+    match object(memory, pointer) {
+        Some(Object::Integer(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+/// The arguments and locals of `frame`, in slot order.
+///
+/// Assumes `LocalFrame::locals`.
+pub fn locals(frame: &LocalFrame) -> &[Pointer] {
+    frame.locals()
+}
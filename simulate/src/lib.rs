@@ -1,16 +1,78 @@
 #![crate_name = "bytecode"]
 
-use crate::debug::PrettyPrint;
-
-mod interpreter;
-mod bytecode;
-mod objects;
-mod types;
-mod serializable;
-mod program;
-mod debug;
-mod io;
-mod compiler;
+pub mod interpreter;
+pub mod bytecode;
+pub mod objects;
+pub mod types;
+pub mod serializable;
+pub mod program;
+pub mod debug;
+pub mod io;
+pub mod compiler;
+pub mod assembler;
+pub mod verifier;
+pub mod debugger;
+mod inspect;
+mod methods;
+pub mod profiler;
+pub mod optimizer;
+pub mod diagnostics;
+pub mod container;
+pub mod limits;
+pub mod cli;
+
+/// Assembly listings shared by several test modules.
+#[cfg(test)]
+mod fixtures {
+    /// Prints "Hello World" and returns null.
+    pub const HELLO: &str = r#"Constants :
+    #0: String("Hello World\n")
+    #1: String("main")
+    #2: Null
+    #3: Method(#1, nargs:0, nlocals:0) :
+          printf #0 0
+          drop
+          lit #2
+          return
+Globals :
+Entry : #3"#;
+
+    /// Prints "ab" on a line of its own, forever.
+    pub const LOOPING: &str = r#"Constants :
+    #0: String("main")
+    #1: String("again")
+    #2: String("ab\n")
+    #3: Method(#0, nargs:0, nlocals:0) :
+       label #1
+          printf #2 0
+          drop
+          goto #1
+Globals :
+Entry : #3"#;
+
+    /// `main` calls `double`, which adds its argument to itself, and prints 42.
+    pub const DOUBLE: &str = r#"Constants :
+    #0: String("double")
+    #1: String("add")
+    #2: Method(#0, nargs:1, nlocals:0) :
+          get local 0
+          get local 0
+          call slot #1 2
+          return
+    #3: String("main")
+    #4: Int(21)
+    #5: String("~\n")
+    #6: String("end")
+    #7: Method(#3, nargs:0, nlocals:0) :
+          lit #4
+          call #0 1
+          printf #5 1
+       label #6
+          return
+Globals :
+    #2
+Entry : #7"#;
+}
 
 #[cfg(test)]
 mod bytecode_deserialization_tests {
@@ -1694,6 +1756,8 @@ mod hello_world_tests {
     use crate::debug::PrettyPrint;
     use std::io::Cursor;
     use crate::interpreter::{interpret, State};
+    use crate::assembler::assemble;
+    use crate::verifier::verify;
 
     fn source() -> &'static str {
         r#"Constants :
@@ -1776,6 +1840,14 @@ Entry : #5"#}
         assert_eq!(&String::from_utf8(bytes).unwrap(), source());
     }
 
+    #[test] fn assemble_source() {
+        assert_eq!(assemble(source()), Ok(program()));
+    }
+
+    #[test] fn verify_program() {
+        assert_eq!(verify(&program()), Ok(()));
+    }
+
     #[test] fn eval() {
         let program = program();
         let mut state = State::from(&program);
@@ -1802,6 +1874,9 @@ mod fibonacci_tests {
     use crate::debug::PrettyPrint;
     use std::io::Cursor;
     use crate::interpreter::{State, interpret};
+    use crate::assembler::assemble;
+    use crate::verifier::verify;
+    use crate::optimizer::{optimize_with_report, Optimizations, DISCARDED_VALUE};
 
     fn source() -> &'static str {
         r#"Constants :
@@ -2146,6 +2221,28 @@ Fib(19) = 6765
         assert_eq!(&String::from_utf8(bytes).unwrap(), source());
     }
 
+    #[test] fn assemble_source() {
+        assert_eq!(assemble(source()), Ok(program()));
+    }
+
+    #[test] fn verify_program() {
+        assert_eq!(verify(&program()), Ok(()));
+    }
+
+    #[test] fn optimize_program() {
+        let (optimized, report) = optimize_with_report(&program(), &Optimizations::all());
+        assert_eq!(report.removed(DISCARDED_VALUE), 2);
+        assert_eq!(report.total(), 2);
+        assert_eq!(verify(&optimized), Ok(()));
+
+        let mut state = State::from(&optimized);
+        let mut output = String::new();
+        while state.instruction_pointer().is_some() {
+            interpret(&mut state, &mut output, &optimized);
+        }
+        assert_eq!(output, expected_output());
+    }
+
     #[test] fn eval() {
         let program = program();
         let mut state = State::from(&program);
@@ -2179,194 +2276,2461 @@ Fib(19) = 6765
 }
 
 #[cfg(test)]
-mod compiler_tests {
-    use fml_ast::{AST, Identifier, Operator};
-    use crate::compiler::Compiled;
-    use crate::program::{Program, Code};
-    use crate::bytecode::OpCode;
-    use crate::compiler::Bookkeeping;
+mod assembler_tests {
+    use crate::assembler::{assemble, AssemblyError};
+    use crate::program::{Code, Program};
     use crate::objects::ProgramObject;
     use crate::types::{ConstantPoolIndex, LocalFrameIndex, Arity, Size, AddressRange};
-    use fml_ast::AST::{Boolean, VariableAccess};
+    use crate::bytecode::OpCode;
+    use crate::debug::PrettyPrint;
 
-    #[test] fn number () {
-        let ast = AST::Number(1);
+    fn print(program: &Program) -> String {
+        let mut bytes: Vec<u8> = Vec::new();
+        program.pretty_print(&mut bytes);
+        String::from_utf8(bytes).unwrap()
+    }
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+    fn error(line: usize, column: usize, message: &str) -> AssemblyError {
+        AssemblyError { line, column, message: message.to_string() }
+    }
 
-        ast.compile_into(&mut program, &mut bookkeeping);
+    #[test] fn every_instruction_and_constant () {
+        let code = Code::from(vec!(
+            /*  0 */ OpCode::Label { name: ConstantPoolIndex::new(0) },
+            /*  1 */ OpCode::Literal { index: ConstantPoolIndex::new(1) },
+            /*  2 */ OpCode::Print { format: ConstantPoolIndex::new(2), arguments: Arity::new(1) },
+            /*  3 */ OpCode::Literal { index: ConstantPoolIndex::new(3) },
+            /*  4 */ OpCode::Literal { index: ConstantPoolIndex::new(4) },
+            /*  5 */ OpCode::Array,
+            /*  6 */ OpCode::Object { class: ConstantPoolIndex::new(7) },
+            /*  7 */ OpCode::GetSlot { name: ConstantPoolIndex::new(5) },
+            /*  8 */ OpCode::SetSlot { name: ConstantPoolIndex::new(5) },
+            /*  9 */ OpCode::CallMethod { name: ConstantPoolIndex::new(5), arguments: Arity::new(2) },
+            /* 10 */ OpCode::CallFunction { name: ConstantPoolIndex::new(8), arguments: Arity::new(0) },
+            /* 11 */ OpCode::SetLocal { index: LocalFrameIndex::new(1) },
+            /* 12 */ OpCode::GetLocal { index: LocalFrameIndex::new(0) },
+            /* 13 */ OpCode::SetGlobal { name: ConstantPoolIndex::new(5) },
+            /* 14 */ OpCode::GetGlobal { name: ConstantPoolIndex::new(5) },
+            /* 15 */ OpCode::Branch { label: ConstantPoolIndex::new(0) },
+            /* 16 */ OpCode::Jump { label: ConstantPoolIndex::new(0) },
+            /* 17 */ OpCode::Drop,
+            /* 18 */ OpCode::Return,
+        ));
 
-        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+        let constants = vec!(
+            /* #0 */ ProgramObject::from_str("start"),
+            /* #1 */ ProgramObject::from_i32(-42),
+            /* #2 */ ProgramObject::from_str("\"~\"\t\\\u{1}\n"),
+            /* #3 */ ProgramObject::from_bool(false),
+            /* #4 */ ProgramObject::Null,
+            /* #5 */ ProgramObject::from_str("x"),
+            /* #6 */ ProgramObject::slot_from_u16(5),
+            /* #7 */ ProgramObject::class_from_vec(vec!(6, 9)),
+            /* #8 */ ProgramObject::from_str("f"),
+            /* #9 */ ProgramObject::Method {
+                name: ConstantPoolIndex::new(8),
+                arguments: Arity::new(1),
+                locals: Size::new(2),
+                code: AddressRange::from(0, 19),
+            },
+            /* #10 */ ProgramObject::class_from_vec(vec!()),
+        );
 
-        let expected_code = Code::from(vec!(
-            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
+        let program = Program::new(code, constants, vec!(ConstantPoolIndex::new(9)), ConstantPoolIndex::new(9));
+        let source = print(&program);
+
+        assert_eq!(assemble(&source), Ok(program));
+        assert_eq!(print(&assemble(&source).unwrap()), source);
+    }
+
+    #[test] fn nested_methods () {
+        // Laid out the way the compiler does it: `h` is nested in `f` and `k`, with the same
+        // body as `h`, in `main`, each behind a function guard.
+        let code = Code::from(vec!(
+            /*  0 */ OpCode::Jump { label: ConstantPoolIndex::new(0) },
+            /*  1 */ OpCode::GetLocal { index: LocalFrameIndex::new(0) },
+            /*  2 */ OpCode::Jump { label: ConstantPoolIndex::new(1) },
+            /*  3 */ OpCode::Literal { index: ConstantPoolIndex::new(2) },
+            /*  4 */ OpCode::Return,
+            /*  5 */ OpCode::Label { name: ConstantPoolIndex::new(1) },
+            /*  6 */ OpCode::Return,
+            /*  7 */ OpCode::Label { name: ConstantPoolIndex::new(0) },
+            /*  8 */ OpCode::Jump { label: ConstantPoolIndex::new(7) },
+            /*  9 */ OpCode::Literal { index: ConstantPoolIndex::new(2) },
+            /* 10 */ OpCode::Return,
+            /* 11 */ OpCode::Label { name: ConstantPoolIndex::new(7) },
+            /* 12 */ OpCode::Literal { index: ConstantPoolIndex::new(2) },
+            /* 13 */ OpCode::Return,
         ));
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::Integer(1)
+        let method = |name: u16, arguments: u8, start: usize, length: usize| ProgramObject::Method {
+            name: ConstantPoolIndex::new(name),
+            arguments: Arity::new(arguments),
+            locals: Size::new(0),
+            code: AddressRange::from(start, length),
+        };
+        let constants = vec!(
+            /* #0 */ ProgramObject::from_str("function_guard_0"),
+            /* #1 */ ProgramObject::from_str("function_guard_1"),
+            /* #2 */ ProgramObject::Null,
+            /* #3 */ ProgramObject::from_str("h"),
+            /* #4 */ method(3, 0, 3, 2),
+            /* #5 */ ProgramObject::from_str("f"),
+            /* #6 */ method(5, 1, 1, 6),
+            /* #7 */ ProgramObject::from_str("function_guard_2"),
+            /* #8 */ ProgramObject::from_str("k"),
+            /* #9 */ method(8, 0, 9, 2),
+            /* #10 */ ProgramObject::from_str("main"),
+            /* #11 */ method(10, 0, 0, 14),
         );
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
-
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+        let program = Program::new(code, constants, vec!(ConstantPoolIndex::new(6), ConstantPoolIndex::new(9)),
+                                   ConstantPoolIndex::new(11));
+        let source = print(&program);
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+        assert_eq!(assemble(&source), Ok(program));
+        assert_eq!(print(&assemble(&source).unwrap()), source);
     }
 
-    #[test] fn some_more_numbers () {
-        let asts = vec!(AST::Number(1), AST::Number(42), AST::Number(0), AST::Number(42));
+    #[test] fn blank_lines_and_indentation_are_ignored () {
+        let source = "\nConstants :\n#0: Null\n\n  #1: String(\"m\")\n#2: Method(#1, nargs:0, nlocals:0) :\nlit #0\n  return\n\nGlobals :\nEntry : #2\n\n";
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+        let expected = Program::new(
+            Code::from(vec!(OpCode::Literal { index: ConstantPoolIndex::new(0) }, OpCode::Return)),
+            vec!(ProgramObject::Null,
+                 ProgramObject::from_str("m"),
+                 ProgramObject::Method { name: ConstantPoolIndex::new(1),
+                                         arguments: Arity::new(0),
+                                         locals: Size::new(0),
+                                         code: AddressRange::from(0, 2) }),
+            vec!(),
+            ConstantPoolIndex::new(2));
 
-        for ast in asts {
-            ast.compile_into(&mut program, &mut bookkeeping);
-        }
+        assert_eq!(assemble(source), Ok(expected));
+    }
 
-        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+    #[test] fn unknown_instruction () {
+        let source = "Constants :\n    #0: Method(#0, nargs:0, nlocals:0) :\n          jump #0\nGlobals :\nEntry : #0";
+        assert_eq!(assemble(source), Err(error(3, 11, "unknown instruction `jump`")));
+    }
 
-        let expected_code = Code::from(vec!(
-            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) },
-            /* 1 */ OpCode::Literal { index: ConstantPoolIndex::new(1) },
-            /* 2 */ OpCode::Literal { index: ConstantPoolIndex::new(2) },
-            /* 3 */ OpCode::Literal { index: ConstantPoolIndex::new(1) },
-        ));
+    #[test] fn constants_out_of_order () {
+        let source = "Constants :\n    #0: Null\n    #2: Null\nGlobals :\nEntry : #0";
+        assert_eq!(assemble(source), Err(error(3, 5, "expected constant #1, found #2")));
+    }
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::Integer(1),
-            /* 1 */ ProgramObject::Integer(42),
-            /* 2 */ ProgramObject::Integer(0),
-        );
+    #[test] fn operand_out_of_range () {
+        let source = "Constants :\n    #0: Method(#0, nargs:0, nlocals:0) :\n          call #0 256\nGlobals :\nEntry : #0";
+        assert_eq!(assemble(source), Err(error(3, 19, "number `256` is out of range")));
+    }
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
+    #[test] fn unterminated_string () {
+        let source = "Constants :\n    #0: String(\"abc)\nGlobals :\nEntry : #0";
+        assert_eq!(assemble(source), Err(error(2, 16, "unterminated string literal")));
+    }
 
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+    #[test] fn missing_entry () {
+        let source = "Constants :\n    #0: Null\nGlobals :\n    #0";
+        assert_eq!(assemble(source), Err(error(4, 1, "unexpected end of input")));
+    }
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+    #[test] fn trailing_input () {
+        let source = "Constants :\n    #0: Int(1) 2\nGlobals :\nEntry : #0";
+        assert_eq!(assemble(source), Err(error(2, 16, "unexpected trailing input")));
     }
+}
 
-    #[test] fn boolean () {
-        let ast = AST::Boolean(true);
+#[cfg(test)]
+mod verifier_tests {
+    use crate::assembler::assemble;
+    use crate::verifier::{verify, Diagnostic, Problem};
+    use crate::program::{Code, Program};
+    use crate::objects::ProgramObject;
+    use crate::bytecode::OpCode;
+    use crate::types::{Address, ConstantPoolIndex, LocalFrameIndex, Arity, Size, AddressRange};
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+    fn diagnostics(source: &str) -> Vec<Diagnostic> {
+        verify(&assemble(source).unwrap()).unwrap_err()
+    }
 
-        ast.compile_into(&mut program, &mut bookkeeping);
+    fn diagnostic(method: Option<&str>, address: Option<usize>, problem: Problem) -> Diagnostic {
+        Diagnostic { method: method.map(|method| method.to_string()),
+                     address: address.map(Address::from_usize),
+                     problem }
+    }
 
-        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+    #[test] fn literal_past_constant_pool () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: Method(#0, nargs:0, nlocals:0) :
+          lit #7
+          return
+Globals :
+Entry : #1"#;
 
-        let expected_code = Code::from(vec!(
-            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
-        ));
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(0), Problem::ConstantOutOfBounds { index: ConstantPoolIndex::new(7) })));
+    }
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::Boolean(true)
-        );
+    #[test] fn wrong_constant_kinds () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: Int(1)
+    #2: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          object #1
+          goto #1
+       label #1
+          return
+Globals :
+    #1
+Entry : #0"#;
+
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(None, None, Problem::WrongConstantKind { index: ConstantPoolIndex::new(1),
+                                                                expected: "Method or Slot",
+                                                                found: "Integer" }),
+            diagnostic(None, None, Problem::WrongConstantKind { index: ConstantPoolIndex::new(0),
+                                                                expected: "Method",
+                                                                found: "String" }),
+            diagnostic(Some("m"), Some(3), Problem::WrongConstantKind { index: ConstantPoolIndex::new(1),
+                                                                        expected: "String",
+                                                                        found: "Integer" }),
+            diagnostic(Some("m"), Some(1), Problem::WrongConstantKind { index: ConstantPoolIndex::new(1),
+                                                                        expected: "Class",
+                                                                        found: "Integer" }),
+            diagnostic(Some("m"), Some(2), Problem::WrongConstantKind { index: ConstantPoolIndex::new(1),
+                                                                        expected: "String",
+                                                                        found: "Integer" })));
+    }
+
+    #[test] fn undefined_and_duplicate_labels () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: String("here")
+    #2: String("there")
+    #3: Null
+    #4: Method(#0, nargs:0, nlocals:0) :
+       label #1
+       label #1
+          goto #2
+          lit #3
+          return
+Globals :
+Entry : #4"#;
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(1), Problem::DuplicateLabel { name: "here".to_string() }),
+            diagnostic(Some("m"), Some(2), Problem::UndefinedLabel { name: "there".to_string() })));
+    }
 
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+    #[test] fn jump_into_another_method () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: String("g")
+    #2: String("inside_f")
+    #3: Null
+    #4: Method(#0, nargs:0, nlocals:0) :
+       label #2
+          lit #3
+          return
+    #5: Method(#1, nargs:0, nlocals:0) :
+          lit #3
+          goto #2
+Globals :
+    #4
+    #5
+Entry : #5"#;
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("g"), Some(4), Problem::LabelOutsideMethod { name: "inside_f".to_string() })));
     }
 
-    #[test] fn unit () {
-        let ast = AST::Unit;
+    #[test] fn method_overruns_code () {
+        let code = Code::from(vec!(OpCode::Literal { index: ConstantPoolIndex::new(1) },
+                                   OpCode::Return));
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+        let constants = vec!(ProgramObject::from_str("m"),
+                             ProgramObject::Null,
+                             ProgramObject::Method { name: ConstantPoolIndex::new(0),
+                                                     arguments: Arity::new(0),
+                                                     locals: Size::new(0),
+                                                     code: AddressRange::from(1, 5) });
 
-        ast.compile_into(&mut program, &mut bookkeeping);
+        let program = Program::new(code, constants, vec!(), ConstantPoolIndex::new(2));
 
-        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+        assert_eq!(verify(&program), Err(vec!(
+            diagnostic(None, None, Problem::MethodOutOfBounds { start: 1, length: 5, code_size: 2 }))));
+    }
 
-        let expected_code = Code::from(vec!(
-            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
-        ));
+    #[test] fn local_out_of_bounds () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: Method(#0, nargs:1, nlocals:1) :
+          get local 1
+          set local 2
+          return
+Globals :
+Entry : #1"#;
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::Null
-        );
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(1), Problem::LocalOutOfBounds { index: LocalFrameIndex::new(2),
+                                                                       frame_size: 2 })));
+    }
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
+    #[test] fn print_format_arity () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: String("~ and ~\n")
+    #2: Method(#0, nargs:0, nlocals:0) :
+          printf #1 0
+          return
+Globals :
+Entry : #2"#;
 
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(0), Problem::FormatArity { placeholders: 2, arguments: 0 })));
+    }
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+    #[test] fn stack_underflow () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: String("add")
+    #2: Int(1)
+    #3: Method(#0, nargs:0, nlocals:0) :
+          lit #2
+          call slot #1 2
+          return
+Globals :
+Entry : #3"#;
+
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(1), Problem::StackUnderflow { depth: 1, required: 2 })));
     }
 
-    #[test] fn local_definition () {
-        let ast = AST::VariableDefinition { name: Identifier::from("x"),
-                                         value: Box::new(AST::Number(1)) };
+    #[test] fn unbalanced_branches () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: String("else")
+    #2: String("end")
+    #3: Bool(true)
+    #4: Int(1)
+    #5: Method(#0, nargs:0, nlocals:0) :
+          lit #3
+          branch #1
+          lit #4
+          lit #4
+          goto #2
+       label #1
+          lit #4
+       label #2
+          return
+Globals :
+Entry : #5"#;
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("m"), Some(8), Problem::ReturnDepth { depth: 2 }),
+            diagnostic(Some("m"), Some(7), Problem::InconsistentStack { expected: 2, found: 1 })));
+    }
 
-        ast.compile_into(&mut program, &mut bookkeeping);
+    #[test] fn return_depth_and_falling_off_the_end () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: String("g")
+    #2: Null
+    #3: Method(#0, nargs:0, nlocals:0) :
+          lit #2
+          lit #2
+          return
+    #4: Method(#1, nargs:0, nlocals:0) :
+          lit #2
+Globals :
+    #3
+Entry : #4"#;
 
-        let expected_bookkeeping = Bookkeeping::from_locals(vec!("x".to_string()));
+        assert_eq!(diagnostics(source), vec!(
+            diagnostic(Some("f"), Some(2), Problem::ReturnDepth { depth: 2 }),
+            diagnostic(Some("g"), Some(3), Problem::FallsOffEnd)));
+    }
 
-        let expected_code = Code::from(vec!(
-            OpCode::Literal { index: ConstantPoolIndex::new(0) },    // value
-            OpCode::SetLocal { index: LocalFrameIndex::new(0) }
+    #[test] fn empty_method () {
+        let source = r#"Constants :
+    #0: String("m")
+    #1: Method(#0, nargs:0, nlocals:0) :
+Globals :
+Entry : #1"#;
+
+        assert_eq!(diagnostics(source), vec!(diagnostic(Some("m"), None, Problem::FallsOffEnd)));
+    }
+
+    #[test] fn function_guard_inside_entry () {
+        let code = Code::from(vec!(
+            /* 0 */ OpCode::Jump { label: ConstantPoolIndex::new(0) },
+            /* 1 */ OpCode::GetLocal { index: LocalFrameIndex::new(0) },
+            /* 2 */ OpCode::Return,
+            /* 3 */ OpCode::Label { name: ConstantPoolIndex::new(0) },
+            /* 4 */ OpCode::Literal { index: ConstantPoolIndex::new(4) },
+            /* 5 */ OpCode::Return,
         ));
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::Integer(1)
+        let constants = vec!(
+            /* 0 */ ProgramObject::from_str("function_guard_0"),
+            /* 1 */ ProgramObject::from_str("id"),
+            /* 2 */ ProgramObject::Method { name: ConstantPoolIndex::new(1),
+                                            arguments: Arity::new(1),
+                                            locals: Size::new(0),
+                                            code: AddressRange::from(1, 2) },
+            /* 3 */ ProgramObject::from_str("entry"),
+            /* 4 */ ProgramObject::Null,
+            /* 5 */ ProgramObject::Method { name: ConstantPoolIndex::new(3),
+                                            arguments: Arity::new(0),
+                                            locals: Size::new(0),
+                                            code: AddressRange::from(0, 6) },
         );
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
-
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+        let program = Program::new(code, constants, vec!(ConstantPoolIndex::new(2)), ConstantPoolIndex::new(5));
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+        assert_eq!(verify(&program), Ok(()));
     }
+}
 
-    #[test] fn global_definition () {
-        let ast = AST::VariableDefinition { name: Identifier::from("x"),
-            value: Box::new(AST::Number(1)) };
+#[cfg(test)]
+mod debugger_tests {
+    use crate::assembler::assemble;
+    use crate::debugger::{session, Debugger};
+    use crate::fixtures;
+    use crate::inspect;
+    use crate::objects::{Object, Pointer};
+    use crate::program::Program;
+    use std::io::Cursor;
 
-        let mut program: Program = Program::empty();
-        let mut bookkeeping: Bookkeeping = Bookkeeping::without_frame();
+    fn program() -> Program {
+        assemble(fixtures::DOUBLE).unwrap()
+    }
 
-        ast.compile_into(&mut program, &mut bookkeeping);
+    fn transcript(script: &str) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        session(&program(), Cursor::new(script), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
 
-        let expected_bookkeeping = Bookkeeping::from_globals(vec!("x".to_string()));
+    fn execute(debugger: &mut Debugger, command: &str) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        debugger.execute(command, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test] fn breakpoints_and_continue () {
+        let script = "break method double\nbreak label end\ncontinue\ncontinue\ncontinue";
+        let expected = r#"main 4: lit #4
+(fml) break method double
+breakpoint at 0
+(fml) break label end
+breakpoint at 7
+(fml) continue
+breakpoint: double 0: get local 0
+(fml) continue
+42
+breakpoint: main 7: label #6
+(fml) continue
+program finished
+"#;
+        assert_eq!(transcript(script), expected);
+    }
+
+    #[test] fn step_over_and_out () {
+        let script = "step\nnext\nbreak 1\ndelete 1\nstep\nstep\nstep\nfinish\nquit\nstep";
+        let expected = r#"main 4: lit #4
+(fml) step
+main 5: call #0 1
+(fml) next
+main 6: printf #5 1
+(fml) break 1
+breakpoint at 1
+(fml) delete 1
+(fml) step
+42
+main 7: label #6
+(fml) step
+main 8: return
+(fml) step
+program finished
+(fml) finish
+program finished
+(fml) quit
+"#;
+        assert_eq!(transcript(script), expected);
+    }
+
+    #[test] fn step_out_of_a_call () {
+        let script = "break 1\ncontinue\nfinish\nwhere";
+        let expected = r#"main 4: lit #4
+(fml) break 1
+breakpoint at 1
+(fml) continue
+breakpoint: double 1: get local 0
+(fml) finish
+main 6: printf #5 1
+(fml) where
+main 6: printf #5 1
+"#;
+        assert_eq!(transcript(script), expected);
+    }
+
+    #[test] fn bad_commands () {
+        let script = "# comment\n\nbreak label nowhere\nbreak method nobody\nbreak 99\nbreak\ndelete 3\nfly";
+        let expected = r#"main 4: lit #4
+(fml) # comment
+(fml) 
+(fml) break label nowhere
+no label named `nowhere`
+(fml) break method nobody
+no method named `nobody`
+(fml) break 99
+address 99 is outside of the code
+(fml) break
+usage: break <address> | label <name> | method <name>
+(fml) delete 3
+no such breakpoint
+(fml) fly
+unknown command `fly`
+"#;
+        assert_eq!(transcript(script), expected);
+    }
+
+    #[test] fn inspect_values () {
+        let program = program();
+        let mut debugger = Debugger::new(&program);
 
-        let expected_code = Code::from(vec!(
-            OpCode::Literal { index: ConstantPoolIndex::new(1) },    // value
-            OpCode::SetGlobal { name: ConstantPoolIndex::new(0) },
-        ));
+        execute(&mut debugger, "break method double");
+        execute(&mut debugger, "continue");
 
-        let expected_constants: Vec<ProgramObject> = vec!(
-            /* 0 */ ProgramObject::from_str("x"),
-            /* 1 */ ProgramObject::from_i32(1),
-        );
+        let argument = &inspect::locals(debugger.state().frames.last().unwrap())[0];
+        let value = format!("{:?} = {:?}\n", argument, Object::from_i32(21));
+        assert_eq!(execute(&mut debugger, "locals"), format!("0: {}", value));
+        assert_eq!(execute(&mut debugger, "heap"), "usage: heap <pointer>\n");
 
-        let expected_globals: Vec<ConstantPoolIndex> = vec!();
-        let expected_entry = ConstantPoolIndex::new(0);
+        execute(&mut debugger, "finish");
 
-        let expected_program =
-            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+        let result = debugger.state().operands.last().unwrap();
+        let value = format!("{:?} = {:?}\n", result, Object::from_i32(42));
+        assert_eq!(execute(&mut debugger, "stack"), value);
+        assert_eq!(execute(&mut debugger, "globals"), "");
 
-        assert_eq!(program, expected_program);
-        assert_eq!(bookkeeping, expected_bookkeeping);
+        let first = Pointer::from(0);
+        let value = format!("{:?} = {:?}\n", first, inspect::object(&debugger.state().memory, &first).unwrap());
+        assert_eq!(execute(&mut debugger, "heap 0"), value);
+        assert_eq!(execute(&mut debugger, "heap 1000"), format!("{:?} is not allocated\n", Pointer::from(1000)));
+    }
+}
+
+#[cfg(test)]
+mod profiler_tests {
+    use crate::assembler::assemble;
+    use crate::fixtures;
+    use crate::interpreter::State;
+    use crate::limits::{LimitExceeded, Limits};
+    use crate::profiler::{profile, profile_with_limits, CallSiteStatistics, MethodStatistics, Profile};
+    use crate::program::Program;
+
+    fn program() -> Program {
+        assemble(fixtures::DOUBLE).unwrap()
+    }
+
+    fn run() -> (Profile, String) {
+        let program = program();
+        let mut state = State::from(&program);
+        let mut output = String::new();
+        let profile = profile(&program, &mut state, &mut output);
+        (profile, output)
+    }
+
+    #[test] fn counts () {
+        let (profile, output) = run();
+        assert_eq!(output, "42\n");
+        assert_eq!(profile.instructions, 9);
+
+        assert_eq!(profile.opcodes.get("GetLocal"), Some(&2));
+        assert_eq!(profile.opcodes.get("Return"), Some(&2));
+        assert_eq!(profile.opcodes.get("CallFunction"), Some(&1));
+        assert_eq!(profile.opcodes.get("CallMethod"), Some(&1));
+        assert_eq!(profile.opcodes.get("Jump"), None);
+
+        assert_eq!(profile.methods.get("main"),
+                   Some(&MethodStatistics { calls: 1, inclusive: 9, exclusive: 5 }));
+        assert_eq!(profile.methods.get("double"),
+                   Some(&MethodStatistics { calls: 1, inclusive: 4, exclusive: 4 }));
+
+        assert_eq!(profile.call_sites.len(), 1);
+        assert_eq!(profile.call_sites.get(&5), Some(&CallSiteStatistics {
+            caller: "main".to_string(), callee: "double".to_string(), calls: 1,
+        }));
+    }
+
+    #[test] fn folded_stacks () {
+        let (profile, _) = run();
+        let mut output: Vec<u8> = Vec::new();
+        profile.folded(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "main 5\nmain;double 4\n");
+    }
+
+    #[test] fn table () {
+        let (profile, _) = run();
+        let mut output: Vec<u8> = Vec::new();
+        profile.table(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines[0], "instructions executed: 9");
+        assert_eq!(lines[3].split_whitespace().collect::<Vec<&str>>(), vec!["GetLocal", "2"]);
+        assert!(output.contains(&format!("{:<24} {:>8} {:>12} {:>12}", "main", 1, 9, 5)));
+        assert!(output.contains(&format!("{:<8} {:<24} {:<24} {:>8}", 5, "main", "double", 1)));
+    }
+
+    #[test] fn limits () {
+        let program = program();
+        let limited = |limits: &Limits| {
+            let mut state = State::from(&program);
+            profile_with_limits(&program, &mut state, limits, |_| {})
+        };
+
+        let (profile, result) = limited(&Limits { instructions: Some(3), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Instructions { limit: 3 }));
+        assert_eq!(profile.instructions, 3);
+
+        // The call runs before the frame limit stops the program, so it is counted.
+        let (profile, result) = limited(&Limits { frames: Some(1), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Frames { limit: 1 }));
+        assert_eq!(profile.instructions, 2);
+        assert_eq!(profile.opcodes.get("CallFunction"), Some(&1));
+        assert_eq!(profile.methods.get("double").map(|method| method.calls), Some(1));
+        assert_eq!(profile.call_sites.get(&5).map(|site| site.calls), Some(1));
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use crate::assembler::assemble;
+    use crate::bytecode::OpCode;
+    use crate::interpreter::{interpret, State};
+    use crate::objects::ProgramObject;
+    use crate::optimizer::*;
+    use crate::program::{Code, Program};
+    use crate::types::{AddressRange, Arity, ConstantPoolIndex, LocalFrameIndex, Size};
+    use crate::verifier::verify;
+
+    fn program(source: &str) -> Program {
+        assemble(source).unwrap()
+    }
+
+    fn folding() -> Optimizations {
+        Optimizations { constant_folding: true, ..Optimizations::none() }
+    }
+
+    fn peephole() -> Optimizations {
+        Optimizations { peephole: true, ..Optimizations::none() }
+    }
+
+    fn dead_code() -> Optimizations {
+        Optimizations { dead_code: true, ..Optimizations::none() }
+    }
+
+    fn tail_calls() -> Optimizations {
+        Optimizations { tail_calls: true, ..Optimizations::none() }
+    }
+
+    #[test] fn fold_arithmetic () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Int(2)
+    #2: Int(5)
+    #3: String("-")
+    #4: String("~\n")
+    #5: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #2
+          call slot #3 2
+          printf #4 1
+          return
+Globals :
+Entry : #5"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: String("~\n")
+    #2: Method(#0, nargs:0, nlocals:0) :
+          lit #3
+          printf #1 1
+          return
+    #3: Int(-3)
+Globals :
+Entry : #2"#;
+
+        assert_eq!(optimize(&program(source), &folding()), program(expected));
+    }
+
+    #[test] fn fold_nested_operations () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Int(2)
+    #2: Int(3)
+    #3: String("+")
+    #4: Int(4)
+    #5: String("mul")
+    #6: Int(21)
+    #7: String("<")
+    #8: Bool(true)
+    #9: String("and")
+    #10: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #2
+          call slot #3 2
+          lit #4
+          call slot #5 2
+          lit #6
+          call slot #7 2
+          lit #8
+          call slot #9 2
+          return
+Globals :
+Entry : #10"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: Bool(true)
+    #2: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          return
+Globals :
+Entry : #2"#;
+
+        assert_eq!(optimize(&program(source), &folding()), program(expected));
+    }
+
+    #[test] fn keep_runtime_errors () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Int(2147483647)
+    #2: Int(1)
+    #3: String("+")
+    #4: Int(0)
+    #5: String("/")
+    #6: Null
+    #7: String("<")
+    #8: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #2
+          call slot #3 2
+          lit #2
+          lit #4
+          call slot #5 2
+          lit #6
+          lit #2
+          call slot #7 2
+          return
+Globals :
+Entry : #8"#;
+
+        assert_eq!(optimize(&program(source), &folding()), program(source));
+    }
+
+    #[test] fn fold_conditionals () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Bool(true)
+    #2: String("taken")
+    #3: Bool(false)
+    #4: String("not_taken")
+    #5: Null
+    #6: Method(#0, nargs:0, nlocals:0) :
+          lit #3
+          branch #4
+          lit #1
+          branch #2
+       label #4
+          lit #5
+          branch #4
+       label #2
+          lit #5
+          return
+Globals :
+Entry : #6"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: String("taken")
+    #2: String("not_taken")
+    #3: Null
+    #4: Method(#0, nargs:0, nlocals:0) :
+          goto #1
+       label #2
+       label #1
+          lit #3
+          return
+Globals :
+Entry : #4"#;
+
+        assert_eq!(optimize(&program(source), &folding()), program(expected));
+    }
+
+    #[test] fn respect_labels_and_methods () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: Int(1)
+    #2: String("+")
+    #3: String("between")
+    #4: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #1
+          call slot #2 2
+          lit #1
+       label #3
+          lit #1
+          call slot #2 2
+          return
+    #5: String("main")
+    #6: Method(#5, nargs:0, nlocals:0) :
+          call #0 0
+          return
+Globals :
+    #4
+Entry : #6"#;
+
+        let expected = r#"Constants :
+    #0: String("f")
+    #1: Int(1)
+    #2: String("+")
+    #3: String("between")
+    #4: Method(#0, nargs:0, nlocals:0) :
+          lit #7
+          lit #1
+       label #3
+          lit #1
+          call slot #2 2
+          return
+    #5: String("main")
+    #6: Method(#5, nargs:0, nlocals:0) :
+          call #0 0
+          return
+    #7: Int(2)
+Globals :
+    #4
+Entry : #6"#;
+
+        assert_eq!(optimize(&program(source), &folding()), program(expected));
+    }
+
+    #[test] fn switched_off () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Int(1)
+    #2: String("+")
+    #3: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #1
+          call slot #2 2
+          return
+Globals :
+Entry : #3"#;
+
+        assert_eq!(optimize(&program(source), &Optimizations::none()), program(source));
+    }
+
+    #[test] fn store_and_reload () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: String("x")
+    #2: Method(#0, nargs:0, nlocals:1) :
+          set local 0
+          drop
+          get local 0
+          set global #1
+          drop
+          get global #1
+          set local 0
+          drop
+          get global #1
+          return
+Globals :
+Entry : #2"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: String("x")
+    #2: Method(#0, nargs:0, nlocals:1) :
+          set local 0
+          set global #1
+          set local 0
+          drop
+          get global #1
+          return
+Globals :
+Entry : #2"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &peephole());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(STORE_AND_RELOAD), 4);
+        assert_eq!(report.total(), 4);
+    }
+
+    #[test] fn discarded_values_and_jumps () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: Null
+    #2: String("next")
+    #3: String("other")
+    #4: Method(#0, nargs:1, nlocals:0) :
+          get local 0
+          drop
+          lit #1
+          lit #1
+          drop
+          drop
+          goto #2
+       label #3
+       label #2
+          lit #1
+          return
+    #5: String("main")
+    #6: Method(#5, nargs:0, nlocals:0) :
+          lit #1
+          call #0 1
+          return
+Globals :
+    #4
+Entry : #6"#;
+
+        let expected = r#"Constants :
+    #0: String("f")
+    #1: Null
+    #2: String("next")
+    #3: String("other")
+    #4: Method(#0, nargs:1, nlocals:0) :
+       label #3
+       label #2
+          lit #1
+          return
+    #5: String("main")
+    #6: Method(#5, nargs:0, nlocals:0) :
+          lit #1
+          call #0 1
+          return
+Globals :
+    #4
+Entry : #6"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &peephole());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(DISCARDED_VALUE), 6);
+        assert_eq!(report.removed(JUMP_TO_NEXT), 1);
+        assert_eq!(report.to_string(), "discarded value: 6 instructions removed\n\
+                                        jump to next instruction: 1 instructions removed\n\
+                                        total: 7 instructions removed");
+    }
+
+    #[test] fn peephole_within_methods () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: Null
+    #2: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+    #3: String("g")
+    #4: Method(#3, nargs:0, nlocals:0) :
+          drop
+          lit #1
+          return
+    #5: String("main")
+    #6: Method(#5, nargs:0, nlocals:0) :
+          lit #1
+          return
+Globals :
+    #2
+    #4
+Entry : #6"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &Optimizations::all());
+        assert_eq!(optimized, program(source));
+        assert_eq!(report.total(), 0);
+    }
+
+    #[test] fn fold_then_simplify () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Int(1)
+    #2: String("==")
+    #3: String("else")
+    #4: String("end")
+    #5: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #1
+          call slot #2 2
+          branch #3
+          lit #1
+          goto #4
+       label #3
+          lit #1
+          lit #1
+          drop
+       label #4
+          return
+Globals :
+Entry : #5"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: Int(1)
+    #2: String("else")
+    #3: String("end")
+    #4: Method(#0, nargs:0, nlocals:0) :
+          goto #2
+          lit #1
+          goto #3
+       label #2
+          lit #1
+       label #3
+          return
+Globals :
+Entry : #4"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &Optimizations { dead_code: false, ..Optimizations::all() });
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(FOLD_OPERATION), 2);
+        assert_eq!(report.removed(FOLD_BRANCH), 1);
+        assert_eq!(report.removed(DISCARDED_VALUE), 2);
+    }
+
+    #[test] fn unreachable_code_and_labels () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Null
+    #2: String("guard")
+    #3: String("unused")
+    #4: String("dead")
+    #5: Method(#0, nargs:0, nlocals:0) :
+          goto #2
+          printf #4 0
+          lit #1
+          return
+       label #2
+       label #3
+          lit #1
+          return
+          lit #1
+Globals :
+Entry : #5"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: Null
+    #2: String("guard")
+    #3: Method(#0, nargs:0, nlocals:0) :
+          goto #2
+       label #2
+          lit #1
+          return
+Globals :
+Entry : #3"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &dead_code());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(UNREACHABLE_CODE), 4);
+        assert_eq!(report.removed(UNUSED_LABEL), 1);
+    }
+
+    #[test] fn dead_methods () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Null
+    #2: String("f")
+    #3: Method(#2, nargs:0, nlocals:0) :
+          lit #1
+          return
+    #4: String("g")
+    #5: Method(#4, nargs:0, nlocals:0) :
+          lit #1
+          return
+    #6: String("m")
+    #7: Method(#6, nargs:1, nlocals:0) :
+          lit #1
+          return
+    #8: Class(#7)
+    #9: String("n")
+    #10: Method(#9, nargs:1, nlocals:0) :
+          lit #1
+          return
+    #11: Class(#10)
+    #12: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          object #8
+          return
+Globals :
+    #3
+Entry : #12"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: Null
+    #2: String("f")
+    #3: Method(#2, nargs:0, nlocals:0) :
+          lit #1
+          return
+    #4: String("m")
+    #5: Method(#4, nargs:1, nlocals:0) :
+          lit #1
+          return
+    #6: Class(#5)
+    #7: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          object #6
+          return
+Globals :
+    #3
+Entry : #7"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &dead_code());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(UNREACHABLE_CODE), 4);
+    }
+
+    #[test] fn all_passes () {
+        let source = r#"Constants :
+    #0: String("main")
+    #1: Bool(true)
+    #2: String("then")
+    #3: String("end")
+    #4: Int(1)
+    #5: Int(2)
+    #6: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          branch #2
+          lit #5
+          goto #3
+       label #2
+          lit #4
+       label #3
+          return
+Globals :
+Entry : #6"#;
+
+        let expected = r#"Constants :
+    #0: String("main")
+    #1: Int(1)
+    #2: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          return
+Globals :
+Entry : #2"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &Optimizations::all());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(report.removed(FOLD_BRANCH), 1);
+        assert_eq!(report.removed(UNREACHABLE_CODE), 2);
+        assert_eq!(report.removed(JUMP_TO_NEXT), 1);
+        assert_eq!(report.removed(UNUSED_LABEL), 2);
+        assert_eq!(report.total(), 6);
+    }
+
+    #[test] fn tail_calls_become_jumps () {
+        let source = r#"Constants :
+    #0: String("count")
+    #1: Int(0)
+    #2: String("==")
+    #3: String("done")
+    #4: Int(1)
+    #5: String("-")
+    #6: String("+")
+    #7: Method(#0, nargs:2, nlocals:1) :
+          get local 0
+          lit #1
+          call slot #2 2
+          branch #3
+          get local 0
+          lit #4
+          call slot #5 2
+          get local 1
+          lit #4
+          call slot #6 2
+          call #0 2
+          return
+       label #3
+          get local 1
+          return
+    #8: String("main")
+    #9: Int(10)
+    #10: Method(#8, nargs:0, nlocals:0) :
+          lit #9
+          lit #1
+          call #0 2
+          return
+Globals :
+    #7
+Entry : #10"#;
+
+        let expected = r#"Constants :
+    #0: String("count")
+    #1: Int(0)
+    #2: String("==")
+    #3: String("done")
+    #4: Int(1)
+    #5: String("-")
+    #6: String("+")
+    #7: Method(#0, nargs:2, nlocals:1) :
+       label #11
+          get local 0
+          lit #1
+          call slot #2 2
+          branch #3
+          get local 0
+          lit #4
+          call slot #5 2
+          get local 1
+          lit #4
+          call slot #6 2
+          set local 1
+          drop
+          set local 0
+          drop
+          lit #12
+          set local 2
+          drop
+          goto #11
+       label #3
+          get local 1
+          return
+    #8: String("main")
+    #9: Int(10)
+    #10: Method(#8, nargs:0, nlocals:0) :
+          lit #9
+          lit #1
+          call #0 2
+          return
+    #11: String("count:tail")
+    #12: Null
+Globals :
+    #7
+Entry : #10"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &tail_calls());
+        assert_eq!(optimized, program(expected));
+        assert_eq!(verify(&optimized), Ok(()));
+        assert_eq!(report.tail_calls(), 1);
+        assert_eq!(report.to_string(), "tail calls: 1 calls replaced with jumps\n\
+                                        total: 0 instructions removed");
+    }
+
+    #[test] fn other_calls_stay () {
+        let source = r#"Constants :
+    #0: String("f")
+    #1: String("g")
+    #2: Method(#0, nargs:1, nlocals:0) :
+          get local 0
+          call #0 1
+          drop
+          get local 0
+          call #1 1
+          return
+    #3: Method(#1, nargs:1, nlocals:0) :
+          get local 0
+          call #1 2
+          return
+    #4: String("main")
+    #5: Null
+    #6: Method(#4, nargs:0, nlocals:0) :
+          lit #5
+          call #0 1
+          return
+    #7: Slot(#0)
+Globals :
+    #2
+    #3
+    #7
+Entry : #6"#;
+
+        let (optimized, report) = optimize_with_report(&program(source), &tail_calls());
+        assert_eq!(optimized, program(source));
+        assert_eq!(report.tail_calls(), 0);
+    }
+
+    #[test] fn nested_methods_keep_their_calls () {
+        // `m` is compiled inside `f` behind a function guard, the way the compiler lays out
+        // methods of objects created in a function. Its call to `f` runs in `m`'s frame.
+        let code = Code::from(vec!(
+            /* 0 */ OpCode::Jump { label: ConstantPoolIndex::new(1) },
+            /* 1 */ OpCode::GetLocal { index: LocalFrameIndex::new(1) },
+            /* 2 */ OpCode::CallFunction { name: ConstantPoolIndex::new(0), arguments: Arity::new(1) },
+            /* 3 */ OpCode::Return,
+            /* 4 */ OpCode::Label { name: ConstantPoolIndex::new(1) },
+            /* 5 */ OpCode::GetLocal { index: LocalFrameIndex::new(0) },
+            /* 6 */ OpCode::CallFunction { name: ConstantPoolIndex::new(0), arguments: Arity::new(1) },
+            /* 7 */ OpCode::Return,
+        ));
+        let constants = vec!(
+            /* 0 */ ProgramObject::from_str("f"),
+            /* 1 */ ProgramObject::from_str("function_guard_0"),
+            /* 2 */ ProgramObject::from_str("m"),
+            /* 3 */ ProgramObject::Method { name: ConstantPoolIndex::new(2),
+                                            arguments: Arity::new(2),
+                                            locals: Size::new(0),
+                                            code: AddressRange::from(1, 3) },
+            /* 4 */ ProgramObject::Method { name: ConstantPoolIndex::new(0),
+                                            arguments: Arity::new(1),
+                                            locals: Size::new(1),
+                                            code: AddressRange::from(0, 8) },
+        );
+        let program = Program::new(code, constants, vec!(ConstantPoolIndex::new(4)), ConstantPoolIndex::new(4));
+
+        let expected_code = Code::from(vec!(
+            /*  0 */ OpCode::Label { name: ConstantPoolIndex::new(5) },
+            /*  1 */ OpCode::Jump { label: ConstantPoolIndex::new(1) },
+            /*  2 */ OpCode::GetLocal { index: LocalFrameIndex::new(1) },
+            /*  3 */ OpCode::CallFunction { name: ConstantPoolIndex::new(0), arguments: Arity::new(1) },
+            /*  4 */ OpCode::Return,
+            /*  5 */ OpCode::Label { name: ConstantPoolIndex::new(1) },
+            /*  6 */ OpCode::GetLocal { index: LocalFrameIndex::new(0) },
+            /*  7 */ OpCode::SetLocal { index: LocalFrameIndex::new(0) },
+            /*  8 */ OpCode::Drop,
+            /*  9 */ OpCode::Literal { index: ConstantPoolIndex::new(6) },
+            /* 10 */ OpCode::SetLocal { index: LocalFrameIndex::new(1) },
+            /* 11 */ OpCode::Drop,
+            /* 12 */ OpCode::Jump { label: ConstantPoolIndex::new(5) },
+        ));
+        let expected_constants = vec!(
+            /* 0 */ ProgramObject::from_str("f"),
+            /* 1 */ ProgramObject::from_str("function_guard_0"),
+            /* 2 */ ProgramObject::from_str("m"),
+            /* 3 */ ProgramObject::Method { name: ConstantPoolIndex::new(2),
+                                            arguments: Arity::new(2),
+                                            locals: Size::new(0),
+                                            code: AddressRange::from(2, 3) },
+            /* 4 */ ProgramObject::Method { name: ConstantPoolIndex::new(0),
+                                            arguments: Arity::new(1),
+                                            locals: Size::new(1),
+                                            code: AddressRange::from(0, 13) },
+            /* 5 */ ProgramObject::from_str("f:tail"),
+            /* 6 */ ProgramObject::Null,
+        );
+        let expected = Program::new(expected_code, expected_constants,
+                                    vec!(ConstantPoolIndex::new(4)), ConstantPoolIndex::new(4));
+
+        let (optimized, report) = optimize_with_report(&program, &tail_calls());
+        assert_eq!(optimized, expected);
+        assert_eq!(report.tail_calls(), 1);
+    }
+
+    /// `loop` counts its argument down to zero, calling itself a million times.
+    const COUNTDOWN: &str = r#"Constants :
+    #0: String("loop")
+    #1: Int(0)
+    #2: String("==")
+    #3: String("done")
+    #4: Int(1)
+    #5: String("-")
+    #6: Method(#0, nargs:1, nlocals:0) :
+          get local 0
+          lit #1
+          call slot #2 2
+          branch #3
+          get local 0
+          lit #4
+          call slot #5 2
+          call #0 1
+          return
+       label #3
+          get local 0
+          return
+    #7: String("main")
+    #8: Int(1000000)
+    #9: String("~\n")
+    #10: Method(#7, nargs:0, nlocals:0) :
+          lit #8
+          call #0 1
+          printf #9 1
+          return
+Globals :
+    #6
+Entry : #10"#;
+
+    /// Runs `program` to the end, returning its output and the deepest the call stack got.
+    fn run_counting_frames(program: &Program) -> (String, usize) {
+        let mut state = State::from(program);
+        let mut output = String::new();
+        let mut deepest = state.frames.len();
+        while state.instruction_pointer().is_some() {
+            interpret(&mut state, &mut output, program);
+            deepest = deepest.max(state.frames.len());
+        }
+        (output, deepest)
+    }
+
+    #[test] fn tail_recursion_in_constant_space () {
+        let (output, deepest) = run_counting_frames(&optimize(&program(COUNTDOWN), &tail_calls()));
+        assert_eq!(output, "0\n");
+        assert!(deepest <= 2, "{} frames deep", deepest);
+    }
+
+    #[test] fn tail_calls_with_every_optimization () {
+        let (optimized, report) = optimize_with_report(&program(COUNTDOWN), &Optimizations::all());
+        assert_eq!(report.tail_calls(), 1);
+        assert_eq!(verify(&optimized), Ok(()));
+        let (output, deepest) = run_counting_frames(&optimized);
+        assert_eq!(output, "0\n");
+        assert!(deepest <= 2, "{} frames deep", deepest);
+    }
+}
+
+#[cfg(test)]
+mod diagnostics_tests {
+    use fml_ast::{AST, Identifier};
+    use crate::compiler;
+    use crate::diagnostics::{check, compile, Diagnostic, Diagnostics, Problem};
+
+    fn function(name: &str, parameters: Vec<&str>, body: AST) -> Box<AST> {
+        Box::new(AST::FunctionDefinition {
+            function: Identifier::from(name),
+            parameters: parameters.into_iter().map(Identifier::from).collect(),
+            body: Box::new(body),
+        })
+    }
+
+    fn call(name: &str, arguments: Vec<AST>) -> Box<AST> {
+        Box::new(AST::FunctionCall {
+            function: Identifier::from(name),
+            arguments: arguments.into_iter().map(Box::new).collect(),
+        })
+    }
+
+    fn let_(name: &str, value: AST) -> Box<AST> {
+        Box::new(AST::VariableDefinition { name: Identifier::from(name), value: Box::new(value) })
+    }
+
+    fn get(name: &str) -> AST {
+        AST::VariableAccess { name: Identifier::from(name) }
+    }
+
+    fn error(function: Option<&str>, problem: Problem) -> Diagnostic {
+        Diagnostic { function: function.map(|function| function.to_string()), problem }
+    }
+
+    #[test] fn clean_program () {
+        let ast = AST::Block(vec!(
+            call("f", vec!(AST::Number(1))),
+            function("f", vec!("x"), AST::Block(vec!(let_("y", get("x")), Box::new(get("y"))))),
+            let_("g", AST::Number(2)),
+            function("h", vec!(), get("g")),
+        ));
+
+        assert_eq!(check(&ast), Diagnostics::default());
+    }
+
+    #[test] fn undefined_variables () {
+        let ast = AST::Block(vec!(
+            function("f", vec!("x"), AST::Block(vec!(
+                Box::new(AST::Block(vec!(let_("y", AST::Number(1)), Box::new(get("y"))))),
+                Box::new(get("y")),
+            ))),
+            Box::new(AST::VariableMutation { name: Identifier::from("z"), value: Box::new(AST::Unit) }),
+        ));
+
+        assert_eq!(check(&ast).errors, vec!(
+            error(Some("f"), Problem::UndefinedVariable { name: "y".to_string() }),
+            error(None, Problem::UndefinedVariable { name: "z".to_string() }),
+        ));
+    }
+
+    #[test] fn unknown_functions_and_arity () {
+        let ast = AST::Block(vec!(
+            function("f", vec!("a", "b"), get("a")),
+            call("f", vec!(AST::Number(1))),
+            call("f", vec!(AST::Number(1), AST::Number(2))),
+            call("g", vec!()),
+        ));
+
+        assert_eq!(check(&ast).errors, vec!(
+            error(None, Problem::WrongArity { function: "f".to_string(), expected: 2, found: 1 }),
+            error(None, Problem::UnknownFunction { name: "g".to_string() }),
+        ));
+    }
+
+    #[test] fn duplicate_parameters_and_members () {
+        let ast = AST::Block(vec!(
+            function("f", vec!("a", "b", "a"), get("b")),
+            Box::new(AST::ObjectDefinition {
+                extends: None,
+                members: vec!(
+                    let_("x", AST::Number(1)),
+                    function("x", vec!(), AST::Unit),
+                    function("m", vec!("this"), AST::Unit),
+                ),
+            }),
+        ));
+
+        assert_eq!(check(&ast).errors, vec!(
+            error(Some("f"), Problem::DuplicateParameter { name: "a".to_string() }),
+            error(None, Problem::DuplicateMember { name: "x".to_string() }),
+            error(Some("m"), Problem::DuplicateParameter { name: "this".to_string() }),
+        ));
+    }
+
+    #[test] fn methods_and_fields () {
+        let ast = AST::ObjectDefinition {
+            extends: None,
+            members: vec!(
+                let_("a", AST::Number(1)),
+                function("get", vec!(), AST::FieldAccess {
+                    object: Box::new(get("this")),
+                    field: Identifier::from("a"),
+                }),
+                function("wrong", vec!(), get("a")),
+            ),
+        };
+
+        assert_eq!(check(&ast).errors, vec!(
+            error(Some("wrong"), Problem::UndefinedVariable { name: "a".to_string() }),
+        ));
+    }
+
+    #[test] fn warnings () {
+        let ast = function("f", vec!("x"), AST::Block(vec!(
+            let_("y", AST::Number(1)),
+            let_("x", AST::Number(2)),
+            Box::new(AST::VariableMutation { name: Identifier::from("y"), value: Box::new(get("x")) }),
+        )));
+
+        let diagnostics = check(&ast);
+        assert_eq!(diagnostics.errors, vec!());
+        assert_eq!(diagnostics.warnings, vec!(
+            error(Some("f"), Problem::Shadowing { name: "x".to_string() }),
+            error(Some("f"), Problem::UnusedLocal { name: "y".to_string() }),
+        ));
+        assert_eq!(diagnostics.to_string(),
+                   "warning: in function `f`: local variable `x` shadows an earlier definition\n\
+                    warning: in function `f`: local variable `y` is never read\n");
+    }
+
+    #[test] fn compile_after_checking () {
+        let ast = function("f", vec!(), AST::Block(vec!(let_("unused", AST::Unit))));
+        let (program, warnings) = compile(&ast).unwrap();
+        assert_eq!(program, compiler::compile(&ast));
+        assert_eq!(warnings, vec!(error(Some("f"), Problem::UnusedLocal { name: "unused".to_string() })));
+
+        let ast = call("f", vec!());
+        assert_eq!(compile(&ast), Err(Diagnostics {
+            errors: vec!(error(None, Problem::UnknownFunction { name: "f".to_string() })),
+            warnings: vec!(),
+        }));
+    }
+}
+
+#[cfg(test)]
+mod container_tests {
+    use crate::assembler::assemble;
+    use crate::container::{crc32, read, write, ContainerError, Header, MAGIC, OPTIMIZED, VERSION};
+    use crate::fixtures;
+    use crate::program::Program;
+    use crate::serializable::Serializable;
+
+    fn program() -> Program {
+        assemble(fixtures::HELLO).unwrap()
+    }
+
+    #[test] fn checksum () {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test] fn round_trip () {
+        let bytes = write(&program(), OPTIMIZED);
+        assert_eq!(&bytes[..4], &MAGIC);
+        assert_eq!(read(&bytes), Ok((program(), Some(Header { version: VERSION, flags: OPTIMIZED }))));
+    }
+
+    #[test] fn legacy () {
+        let mut bytes: Vec<u8> = Vec::new();
+        program().serialize(&mut bytes);
+        assert_eq!(read(&bytes), Ok((program(), None)));
+    }
+
+    #[test] fn mismatches () {
+        let bytes = write(&program(), 0);
+
+        assert_eq!(read(&bytes[..10]), Err(ContainerError::Truncated { expected: 16, found: 10 }));
+        assert_eq!(read(&bytes[..bytes.len() - 1]),
+                   Err(ContainerError::Truncated { expected: bytes.len(), found: bytes.len() - 1 }));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(read(&trailing), Err(ContainerError::TrailingBytes { expected: bytes.len(), found: bytes.len() + 1 }));
+
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert_eq!(read(&version), Err(ContainerError::UnsupportedVersion { version: 2 }));
+
+        let mut flags = bytes.clone();
+        flags[7] = 0x80;
+        assert_eq!(read(&flags), Err(ContainerError::UnknownFlags { flags: 0x8000 }));
+
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xFF;
+        match read(&corrupted) {
+            Err(ContainerError::ChecksumMismatch { expected, found }) => {
+                assert_eq!(expected, crc32(&bytes[16..]));
+                assert_eq!(found, crc32(&corrupted[16..]));
+            }
+            result => panic!("expected a checksum mismatch, got {:?}", result),
+        }
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use crate::assembler::assemble;
+    use crate::cli::{main, parse_arguments, Command, Failure, Options};
+    use crate::container::write;
+    use crate::fixtures::{HELLO, LOOPING};
+    use crate::limits::Limits;
+    use crate::serializable::Serializable;
+    use std::env;
+    use std::fs;
+    use std::ops::Deref;
+    use std::process;
+
+    fn arguments(arguments: &[&str]) -> Vec<String> {
+        arguments.iter().map(|argument| argument.to_string()).collect()
+    }
+
+    /// The path of a file in the temporary directory, which is deleted again once dropped.
+    struct TemporaryFile(String);
+
+    impl Deref for TemporaryFile {
+        type Target = str;
+
+        fn deref(&self) -> &str {
+            &self.0
+        }
+    }
+
+    impl Drop for TemporaryFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn file(name: &str, bytes: &[u8]) -> TemporaryFile {
+        let path = env::temp_dir().join(format!("simulate-cli-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        TemporaryFile(path.to_str().unwrap().to_string())
+    }
+
+    fn bytecode(name: &str, source: &str) -> TemporaryFile {
+        let mut bytes: Vec<u8> = Vec::new();
+        assemble(source).unwrap().serialize(&mut bytes);
+        file(name, &bytes)
+    }
+
+    fn run(command: &[&str]) -> (i32, String, String) {
+        let (mut out, mut err): (Vec<u8>, Vec<u8>) = (Vec::new(), Vec::new());
+        let code = main(&arguments(command), &mut out, &mut err);
+        (code, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    #[test] fn options () {
+        assert_eq!(parse_arguments(&arguments(&["run", "--verbose", "hello.fml", "--dump-ast"])), Ok(Command {
+            name: "run".to_string(),
+            input: "hello.fml".to_string(),
+            options: Options { verbose: true, dump_ast: true, ..Options::default() },
+        }));
+        assert_eq!(parse_arguments(&arguments(&["compile", "-", "-o", "hello.bc", "--no-optimize"])), Ok(Command {
+            name: "compile".to_string(),
+            input: "-".to_string(),
+            options: Options { no_optimize: true, output: Some("hello.bc".to_string()), ..Options::default() },
+        }));
+        assert_eq!(parse_arguments(&arguments(&["execute", "hello.bc", "--max-instructions", "1000", "--max-depth", "64", "--max-elements", "4096"])), Ok(Command {
+            name: "execute".to_string(),
+            input: "hello.bc".to_string(),
+            options: Options {
+                limits: Limits { instructions: Some(1000), frames: Some(64), elements: Some(4096), ..Limits::none() },
+                ..Options::default()
+            },
+        }));
+    }
+
+    #[test] fn usage_errors () {
+        let usage = |command: &[&str]| match parse_arguments(&arguments(command)) {
+            Err(Failure::Usage(message)) => message,
+            result => panic!("expected a usage error, got {:?}", result),
+        };
+        assert_eq!(usage(&[]), "no command given");
+        assert_eq!(usage(&["run"]), "`run` needs a file");
+        assert_eq!(usage(&["run", "a.fml", "b.fml"]), "unexpected argument `b.fml`");
+        assert_eq!(usage(&["walk", "a.fml"]), "unknown command `walk`");
+        assert_eq!(usage(&["run", "a.fml", "--fast"]), "unknown option `--fast`");
+        assert_eq!(usage(&["compile", "a.fml"]), "compile needs an output file given with -o");
+        assert_eq!(usage(&["compile", "a.fml", "-o"]), "-o needs a file name");
+        assert_eq!(usage(&["execute", "a.bc", "-o", "b.bc"]), "execute does not write an output file");
+        assert_eq!(usage(&["execute", "a.bc", "--max-objects"]), "--max-objects needs a number");
+        assert_eq!(usage(&["execute", "a.bc", "--max-output", "lots"]), "--max-output needs a number");
+        assert_eq!(usage(&["disassemble", "a.bc", "--max-depth", "8"]), "disassemble does not run the program, so it takes no limits");
+
+        let (code, out, err) = run(&["run"]);
+        assert_eq!((code, out.as_str()), (2, ""));
+        assert!(err.starts_with("`run` needs a file\n\nusage: simulate <command> [options] <file>\n"));
+    }
+
+    #[test] fn execute_and_disassemble () {
+        let path = bytecode("hello.bc", HELLO);
+
+        assert_eq!(run(&["execute", &path]), (0, "Hello World\n".to_string(), String::new()));
+        assert_eq!(run(&["disassemble", &path]), (0, format!("{}\n", HELLO), String::new()));
+
+        let (code, out, err) = run(&["execute", &path, "--profile"]);
+        assert_eq!((code, out.as_str()), (0, "Hello World\n"));
+        assert!(err.starts_with("instructions executed: 4\n"));
+    }
+
+    #[test] fn containers () {
+        let path = file("hello-container.bc", &write(&assemble(HELLO).unwrap(), 0));
+        assert_eq!(run(&["execute", &path]), (0, "Hello World\n".to_string(), String::new()));
+
+        let mut bytes = write(&assemble(HELLO).unwrap(), 0);
+        bytes.truncate(bytes.len() - 1);
+        let path = file("hello-truncated.bc", &bytes);
+        let (code, _, err) = run(&["disassemble", &path]);
+        assert_eq!(code, 6);
+        assert!(err.starts_with("invalid bytecode: container is truncated"));
+    }
+
+    #[test] fn failures () {
+        let (code, _, err) = run(&["execute", &file("missing.bc", &[])]);
+        assert_eq!(code, 6);
+        assert!(err.starts_with("invalid bytecode: "));
+
+        let unbalanced = bytecode("unbalanced.bc", r#"Constants :
+    #0: String("main")
+    #1: Method(#0, nargs:0, nlocals:0) :
+          drop
+          return
+Globals :
+Entry : #1"#);
+        assert_eq!(run(&["execute", &unbalanced]), (6, String::new(), "\
+invalid bytecode: in method `main` at 0: instruction needs 1 operands but the stack holds 0
+".to_string()));
+
+        let looping = bytecode("looping.bc", LOOPING);
+        assert_eq!(run(&["execute", &looping, "--max-instructions", "7"]),
+                   (8, "ab\nab\n".to_string(), "stopped: program did not finish within 7 instructions\n".to_string()));
+        assert_eq!(run(&["execute", &looping, "--max-output", "4"]),
+                   (8, "ab\na".to_string(), "stopped: program printed more than 4 bytes\n".to_string()));
+        let (code, out, err) = run(&["execute", &looping, "--max-instructions", "7", "--profile"]);
+        assert_eq!((code, out.as_str()), (8, "ab\nab\n"));
+        assert!(err.starts_with("instructions executed: 7\n"));
+        assert!(err.ends_with("stopped: program did not finish within 7 instructions\n"));
+
+        let nonexistent = env::temp_dir().join("simulate-cli-nonexistent.bc");
+        let (code, _, err) = run(&["disassemble", nonexistent.to_str().unwrap()]);
+        assert_eq!(code, 3);
+        assert!(err.starts_with("cannot read "));
+    }
+}
+
+#[cfg(test)]
+mod round_trip_tests {
+    use std::cmp::Ordering;
+    use std::io::Cursor;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use crate::assembler::assemble;
+    use crate::bytecode::OpCode;
+    use crate::debug::PrettyPrint;
+    use crate::objects::ProgramObject;
+    use crate::optimizer::{with_constant_operand, with_constant_references};
+    use crate::program::{Code, Program};
+    use crate::serializable::{Serializable, SerializableWithContext};
+    use crate::types::{AddressRange, Arity, ConstantPoolIndex, LocalFrameIndex, Size};
+
+    const CASES: usize = 500;
+
+    /// Xorshift generator, seeded per test so that every failure reproduces.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+
+        fn index(&mut self, constants: usize) -> ConstantPoolIndex {
+            ConstantPoolIndex::new(self.below(constants) as u16)
+        }
+
+        fn integer(&mut self) -> i32 {
+            match self.below(4) {
+                0 => [0, 1, -1, i32::MIN, i32::MAX][self.below(5)],
+                1 => self.below(100) as i32 - 50,
+                _ => self.next() as i32,
+            }
+        }
+
+        fn string(&mut self) -> String {
+            const CHARACTERS: &[char] = &['a', 'Z', '0', ' ', '#', ':', '(', ')', ',', '"', '\'', '\\',
+                                          '\n', '\r', '\t', '\0', '\u{1b}', '\u{7f}', 'é', 'λ', '🦀'];
+            (0..self.below(12)).map(|_| CHARACTERS[self.below(CHARACTERS.len())]).collect()
+        }
+
+        /// Any instruction but `Skip`, which has no encoding in the bytecode format.
+        fn opcode(&mut self, constants: usize) -> OpCode {
+            match self.below(17) {
+                0 => OpCode::Label { name: self.index(constants) },
+                1 => OpCode::Literal { index: self.index(constants) },
+                2 => OpCode::Print { format: self.index(constants), arguments: Arity::new(self.next() as u8) },
+                3 => OpCode::Array,
+                4 => OpCode::Object { class: self.index(constants) },
+                5 => OpCode::GetSlot { name: self.index(constants) },
+                6 => OpCode::SetSlot { name: self.index(constants) },
+                7 => OpCode::CallMethod { name: self.index(constants), arguments: Arity::new(self.next() as u8) },
+                8 => OpCode::CallFunction { name: self.index(constants), arguments: Arity::new(self.next() as u8) },
+                9 => OpCode::SetLocal { index: LocalFrameIndex::new(self.next() as u16) },
+                10 => OpCode::GetLocal { index: LocalFrameIndex::new(self.next() as u16) },
+                11 => OpCode::SetGlobal { name: self.index(constants) },
+                12 => OpCode::GetGlobal { name: self.index(constants) },
+                13 => OpCode::Branch { label: self.index(constants) },
+                14 => OpCode::Jump { label: self.index(constants) },
+                15 => OpCode::Return,
+                _ => OpCode::Drop,
+            }
+        }
+
+        /// Any constant but a method, whose code lives outside of the object.
+        fn object(&mut self, constants: usize) -> ProgramObject {
+            match self.below(6) {
+                0 => ProgramObject::Null,
+                1 => ProgramObject::Integer(self.integer()),
+                2 => ProgramObject::Boolean(self.below(2) == 0),
+                3 => ProgramObject::String(self.string()),
+                4 => ProgramObject::Slot { name: self.index(constants) },
+                _ => ProgramObject::Class((0..self.below(4)).map(|_| self.index(constants)).collect()),
+            }
+        }
+
+        fn method(&mut self, constants: usize) -> Constant {
+            Constant::Method {
+                name: self.below(constants) as u16,
+                arguments: self.next() as u8,
+                locals: self.next() as u16,
+                body: (0..self.below(8)).map(|_| self.opcode(constants)).collect(),
+                guard: None,
+            }
+        }
+
+        /// A random program, with some methods nested inside earlier ones if `nested` is set.
+        fn case(&mut self, nested: bool) -> Case {
+            let count = 1 + self.below(10);
+            let mut constants = Vec::new();
+            for index in 0..count {
+                let mut constant = match self.below(3) {
+                    0 => self.method(count),
+                    _ => Constant::Object(self.object(count)),
+                };
+                if let Constant::Method { guard, .. } = &mut constant {
+                    if nested && index > 0 && self.below(2) == 0 {
+                        *guard = Some((self.below(index), self.below(count) as u16));
+                    }
+                }
+                constants.push(constant);
+            }
+
+            // The assembler tells a nested method by its body, so only unique bodies can nest.
+            let bodies: Vec<Vec<OpCode>> = constants.iter()
+                .filter_map(|constant| match constant {
+                    Constant::Method { body, .. } => Some(body.clone()),
+                    _ => None,
+                })
+                .collect();
+            for constant in constants.iter_mut() {
+                if let Constant::Method { body, guard, .. } = constant {
+                    if body.is_empty() || bodies.iter().filter(|other| *other == body).count() > 1 {
+                        *guard = None;
+                    }
+                }
+            }
+
+            let globals = (0..self.below(4)).map(|_| self.below(count) as u16).collect();
+            let entry = self.below(count) as u16;
+            Case { constants, globals, entry }
+        }
+    }
+
+    /// A constant pool entry, with method bodies kept next to their method so that shrinking
+    /// can drop constants and instructions without recomputing code ranges by hand.
+    /// A method's `guard` names an earlier method whose code it is laid out in, after the
+    /// earlier method's own body and between `goto` and `label` instructions for the given
+    /// label, the way the compiler nests functions.
+    #[derive(Clone)]
+    enum Constant {
+        Object(ProgramObject),
+        Method { name: u16, arguments: u8, locals: u16, body: Vec<OpCode>, guard: Option<(usize, u16)> },
+    }
+
+    #[derive(Clone)]
+    struct Case {
+        constants: Vec<Constant>,
+        globals: Vec<u16>,
+        entry: u16,
+    }
+
+    impl Case {
+        /// Lays out the bodies of methods that are not nested one after another in constant pool
+        /// order, each followed by the methods nested in it. Only the listing can reproduce
+        /// nested methods; the bytecode format lays every body out separately.
+        fn program(&self) -> Program {
+            let mut code: Vec<OpCode> = Vec::new();
+            let mut ranges: Vec<(usize, usize)> = vec!((0, 0); self.constants.len());
+            for index in 0..self.constants.len() {
+                if self.outer(index).is_none() {
+                    self.lay_out(index, &mut code, &mut ranges);
+                }
+            }
+
+            let constants = self.constants.iter().zip(ranges).map(|(constant, (start, length))| match constant {
+                Constant::Object(object) => object.clone(),
+                Constant::Method { name, arguments, locals, .. } => ProgramObject::Method {
+                    name: ConstantPoolIndex::new(*name),
+                    arguments: Arity::new(*arguments),
+                    locals: Size::new(*locals),
+                    code: AddressRange::from(start, length),
+                },
+            }).collect();
+            let globals = self.globals.iter().map(|global| ConstantPoolIndex::new(*global)).collect();
+            Program::new(Code::from(code), constants, globals, ConstantPoolIndex::new(self.entry))
+        }
+
+        /// The earlier method that the method at `index` is nested in, and the label of its guard.
+        fn outer(&self, index: usize) -> Option<(usize, u16)> {
+            match &self.constants[index] {
+                Constant::Method { guard: Some((outer, label)), .. } if *outer < index => match &self.constants[*outer] {
+                    Constant::Method { .. } => Some((*outer, *label)),
+                    Constant::Object(_) => None,
+                },
+                _ => None,
+            }
+        }
+
+        /// Appends the body of the method at `index` and the methods nested in it to `code`.
+        fn lay_out(&self, index: usize, code: &mut Vec<OpCode>, ranges: &mut Vec<(usize, usize)>) {
+            if let Constant::Method { body, .. } = &self.constants[index] {
+                let start = code.len();
+                code.extend(body.iter().cloned());
+                for inner in index + 1..self.constants.len() {
+                    if let Some((outer, label)) = self.outer(inner) {
+                        if outer == index {
+                            code.push(OpCode::Jump { label: ConstantPoolIndex::new(label) });
+                            self.lay_out(inner, code, ranges);
+                            code.push(OpCode::Label { name: ConstantPoolIndex::new(label) });
+                        }
+                    }
+                }
+                ranges[index] = (start, code.len() - start);
+            }
+        }
+
+        /// Removes one constant, pointing references to it at #0 and renumbering the rest.
+        fn without_constant(&self, removed: usize) -> Case {
+            let renumber = |index: u16| match (index as usize).cmp(&removed) {
+                Ordering::Less => index,
+                Ordering::Equal => 0,
+                Ordering::Greater => index - 1,
+            };
+            let reference = |index: &ConstantPoolIndex| ConstantPoolIndex::new(renumber(index.value()));
+
+            let constants = self.constants.iter().enumerate()
+                .filter(|(index, _)| *index != removed)
+                .map(|(_, constant)| match constant {
+                    Constant::Object(object) => Constant::Object(with_constant_references(object.clone(), reference)),
+                    Constant::Method { name, arguments, locals, body, guard } => Constant::Method {
+                        name: renumber(*name),
+                        arguments: *arguments,
+                        locals: *locals,
+                        body: body.iter().map(|opcode| with_constant_operand(opcode.clone(), reference)).collect(),
+                        guard: guard.filter(|(outer, _)| *outer != removed)
+                            .map(|(outer, label)| (renumber(outer as u16) as usize, renumber(label))),
+                    },
+                })
+                .collect();
+            let globals = self.globals.iter().map(|global| renumber(*global)).collect();
+            Case { constants, globals, entry: renumber(self.entry) }
+        }
+
+        /// Every case one step simpler than this one, biggest steps first.
+        fn simplifications(&self) -> Vec<Case> {
+            let mut cases = Vec::new();
+            if self.constants.len() > 1 {
+                cases.extend((0..self.constants.len()).map(|index| self.without_constant(index)));
+            }
+
+            for (index, constant) in self.constants.iter().enumerate() {
+                let simpler = match constant {
+                    Constant::Object(ProgramObject::Null) => continue,
+                    Constant::Object(_) => Constant::Object(ProgramObject::Null),
+                    Constant::Method { body, .. } if body.is_empty() => Constant::Object(ProgramObject::Null),
+                    Constant::Method { .. } => continue,
+                };
+                let mut case = self.clone();
+                case.constants[index] = simpler;
+                cases.push(case);
+            }
+
+            for index in 0..self.globals.len() {
+                let mut case = self.clone();
+                case.globals.remove(index);
+                cases.push(case);
+            }
+
+            for index in 0..self.constants.len() {
+                if self.outer(index).is_some() {
+                    let mut case = self.clone();
+                    if let Constant::Method { guard, .. } = &mut case.constants[index] {
+                        *guard = None;
+                    }
+                    cases.push(case);
+                }
+            }
+
+            for (index, constant) in self.constants.iter().enumerate() {
+                if let Constant::Method { name, arguments, locals, body, guard } = constant {
+                    for instruction in 0..body.len() {
+                        let mut case = self.clone();
+                        if let Constant::Method { body, .. } = &mut case.constants[index] {
+                            body.remove(instruction);
+                        }
+                        cases.push(case);
+                    }
+                    if (*name, *arguments, *locals) != (0, 0, 0) {
+                        let mut case = self.clone();
+                        case.constants[index] = Constant::Method { name: 0, arguments: 0, locals: 0, body: body.clone(), guard: *guard };
+                        cases.push(case);
+                    }
+                }
+            }
+
+            if self.entry != 0 {
+                cases.push(Case { entry: 0, ..self.clone() });
+            }
+            cases
+        }
+    }
+
+    /// Whether `property` holds for `case`, counting a panic as a failure.
+    fn holds<P: Fn(&Program) -> bool>(property: &P, case: &Case) -> bool {
+        let program = case.program();
+        panic::catch_unwind(AssertUnwindSafe(|| property(&program))).unwrap_or(false)
+    }
+
+    /// Greedily applies simplifications for as long as the property keeps failing.
+    fn shrink<P: Fn(&Program) -> bool>(mut case: Case, property: &P) -> Case {
+        while let Some(simpler) = case.simplifications().into_iter().find(|simpler| !holds(property, simpler)) {
+            case = simpler;
+        }
+        case
+    }
+
+    /// The smallest counterexample to `property` found among random programs, with nested
+    /// methods if `nested` is set, if any.
+    fn counterexample<P: Fn(&Program) -> bool>(seed: u64, nested: bool, property: P) -> Option<Case> {
+        let mut random = Random(seed);
+        (0..CASES).map(|_| random.case(nested))
+            .find(|case| !holds(&property, case))
+            .map(|case| shrink(case, &property))
+    }
+
+    fn check<P: Fn(&Program) -> bool>(seed: u64, nested: bool, property: P) {
+        if let Some(case) = counterexample(seed, nested, property) {
+            panic!("property does not hold for:\n{}", listing(&case.program()));
+        }
+    }
+
+    fn listing(program: &Program) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        program.pretty_print(&mut output);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test] fn opcodes () {
+        let mut random = Random(0x0BC0_DE5E_ED00_0001);
+        for _ in 0..CASES {
+            let opcode = random.opcode(u16::MAX as usize + 1);
+            let mut bytes: Vec<u8> = Vec::new();
+            opcode.serialize(&mut bytes);
+            assert_eq!(OpCode::from_bytes(&mut Cursor::new(bytes)), opcode);
+        }
+    }
+
+    #[test] fn program_objects () {
+        let mut random = Random(0x0BC0_DE5E_ED00_0002);
+        for _ in 0..CASES {
+            let object = random.object(u16::MAX as usize + 1);
+            let mut bytes: Vec<u8> = Vec::new();
+            object.serialize(&mut bytes, &Code::new());
+
+            let mut code = Code::new();
+            assert_eq!(ProgramObject::from_bytes(&mut Cursor::new(bytes), &mut code), object);
+            assert_eq!(code, Code::new());
+        }
+    }
+
+    #[test] fn methods () {
+        let mut random = Random(0x0BC0_DE5E_ED00_0003);
+        for _ in 0..CASES {
+            let program = Case { constants: vec!(random.method(1)), globals: vec!(), entry: 0 }.program();
+            let method = &program.constants()[0];
+            let mut bytes: Vec<u8> = Vec::new();
+            method.serialize(&mut bytes, program.code());
+
+            let mut code = Code::new();
+            assert_eq!(&ProgramObject::from_bytes(&mut Cursor::new(bytes), &mut code), method);
+            assert_eq!(&code, program.code());
+        }
+    }
+
+    #[test] fn serialize_and_deserialize () {
+        check(0x0BC0_DE5E_ED00_0004, false, |program| {
+            let mut bytes: Vec<u8> = Vec::new();
+            program.serialize(&mut bytes);
+            Program::from_bytes(&mut Cursor::new(bytes)) == *program
+        });
+    }
+
+    #[test] fn pretty_print_and_assemble () {
+        check(0x0BC0_DE5E_ED00_0005, true, |program| {
+            matches!(assemble(&listing(program)), Ok(assembled) if assembled == *program)
+        });
+    }
+
+    #[test] fn shrinking () {
+        let without_drops = |program: &Program| !program.code().as_vec().contains(&OpCode::Drop);
+        let minimal = counterexample(0x0BC0_DE5E_ED00_0006, false, without_drops).unwrap();
+        assert_eq!(Ok(minimal.program()), assemble(r#"Constants :
+    #0: Method(#0, nargs:0, nlocals:0) :
+          drop
+Globals :
+Entry : #0"#));
+    }
+}
+
+#[cfg(test)]
+mod limits_tests {
+    use crate::assembler::assemble;
+    use crate::fixtures::{HELLO, LOOPING};
+    use crate::inspect;
+    use crate::interpreter::State;
+    use crate::limits::{run, run_flushing, LimitExceeded, Limits};
+    use crate::program::Program;
+
+    fn program(source: &str) -> Program {
+        assemble(source).unwrap()
+    }
+
+    fn execute(program: &Program, limits: &Limits) -> (State, String, Result<(), LimitExceeded>) {
+        let mut state = State::from(program);
+        let mut output = String::new();
+        let result = run(program, &mut state, &mut output, limits);
+        (state, output, result)
+    }
+
+    #[test] fn unlimited () {
+        let hello = program(HELLO);
+        let (state, output, result) = execute(&hello, &Limits::none());
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, "Hello World\n");
+        assert_eq!(state.instruction_pointer, None);
+
+        let limits = Limits { instructions: Some(4), objects: Some(2), elements: Some(0), frames: Some(1), output: Some(12) };
+        assert_eq!(execute(&hello, &limits).2, Ok(()));
+    }
+
+    #[test] fn instructions () {
+        let (state, output, result) = execute(&program(LOOPING), &Limits { instructions: Some(10), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Instructions { limit: 10 }));
+        assert_eq!(output, "ab\nab\nab\n");
+        assert!(state.instruction_pointer.is_some());
+    }
+
+    #[test] fn output () {
+        let (_, output, result) = execute(&program(LOOPING), &Limits { output: Some(10), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Output { limit: 10 }));
+        assert_eq!(output, "ab\nab\nab\na");
+    }
+
+    #[test] fn flushing () {
+        let looping = program(LOOPING);
+        let mut state = State::from(&looping);
+        let mut flushed: Vec<String> = Vec::new();
+        let limits = Limits { output: Some(10), ..Limits::none() };
+        let result = run_flushing(&looping, &mut state, &limits, |printed| flushed.push(printed.to_string()));
+        assert_eq!(result, Err(LimitExceeded::Output { limit: 10 }));
+        assert_eq!(flushed, vec!("ab\n", "ab\n", "ab\n", "a"));
+    }
+
+    #[test] fn objects () {
+        let allocating = program(r#"Constants :
+    #0: String("main")
+    #1: String("again")
+    #2: Int(42)
+    #3: Method(#0, nargs:0, nlocals:0) :
+       label #1
+          lit #2
+          drop
+          goto #1
+Globals :
+Entry : #3"#);
+        let (state, _, result) = execute(&allocating, &Limits { objects: Some(10), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Objects { limit: 10 }));
+        assert_eq!(inspect::allocated(&state.memory, 0), 11);
+    }
+
+    #[test] fn frames () {
+        let recursive = program(r#"Constants :
+    #0: String("f")
+    #1: Method(#0, nargs:0, nlocals:0) :
+          call #0 0
+          return
+    #2: String("main")
+    #3: Method(#2, nargs:0, nlocals:0) :
+          call #0 0
+          return
+Globals :
+    #1
+Entry : #3"#);
+        let (state, _, result) = execute(&recursive, &Limits { frames: Some(5), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Frames { limit: 5 }));
+        assert_eq!(state.frames.len(), 6);
+    }
+
+    #[test] fn arrays () {
+        let arrays = |size| program(&format!(r#"Constants :
+    #0: String("main")
+    #1: Int({})
+    #2: Null
+    #3: Method(#0, nargs:0, nlocals:0) :
+          lit #1
+          lit #2
+          array
+          drop
+          lit #2
+          return
+Globals :
+Entry : #3"#, size));
+
+        let huge = arrays(i32::MAX);
+        let (state, _, result) = execute(&huge, &Limits { objects: Some(10), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Objects { limit: 10 }));
+        assert!(inspect::allocated(&state.memory, 0) <= 10);
+        assert!(state.instruction_pointer.is_some());
+
+        let (state, _, result) = execute(&huge, &Limits { elements: Some(100), ..Limits::none() });
+        assert_eq!(result, Err(LimitExceeded::Elements { limit: 100 }));
+        assert!(inspect::allocated(&state.memory, 0) <= 10);
+
+        assert_eq!(execute(&arrays(3), &Limits { elements: Some(3), ..Limits::none() }).2, Ok(()));
+        assert_eq!(execute(&arrays(4), &Limits { elements: Some(3), ..Limits::none() }).2,
+                   Err(LimitExceeded::Elements { limit: 3 }));
+    }
+}
+
+#[cfg(test)]
+mod compiler_tests {
+    use fml_ast::{AST, Identifier, Operator};
+    use crate::compiler::Compiled;
+    use crate::program::{Program, Code};
+    use crate::bytecode::OpCode;
+    use crate::compiler::Bookkeeping;
+    use crate::objects::ProgramObject;
+    use crate::types::{ConstantPoolIndex, LocalFrameIndex, Arity, Size, AddressRange};
+    use fml_ast::AST::{Boolean, VariableAccess};
+
+    #[test] fn number () {
+        let ast = AST::Number(1);
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        ast.compile_into(&mut program, &mut bookkeeping);
+
+        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        let expected_code = Code::from(vec!(
+            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::Integer(1)
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
+    }
+
+    #[test] fn some_more_numbers () {
+        let asts = vec!(AST::Number(1), AST::Number(42), AST::Number(0), AST::Number(42));
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        for ast in asts {
+            ast.compile_into(&mut program, &mut bookkeeping);
+        }
+
+        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        let expected_code = Code::from(vec!(
+            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) },
+            /* 1 */ OpCode::Literal { index: ConstantPoolIndex::new(1) },
+            /* 2 */ OpCode::Literal { index: ConstantPoolIndex::new(2) },
+            /* 3 */ OpCode::Literal { index: ConstantPoolIndex::new(1) },
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::Integer(1),
+            /* 1 */ ProgramObject::Integer(42),
+            /* 2 */ ProgramObject::Integer(0),
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
+    }
+
+    #[test] fn boolean () {
+        let ast = AST::Boolean(true);
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        ast.compile_into(&mut program, &mut bookkeeping);
+
+        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        let expected_code = Code::from(vec!(
+            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::Boolean(true)
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
+    }
+
+    #[test] fn unit () {
+        let ast = AST::Unit;
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        ast.compile_into(&mut program, &mut bookkeeping);
+
+        let expected_bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        let expected_code = Code::from(vec!(
+            /* 0 */ OpCode::Literal { index: ConstantPoolIndex::new(0) }
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::Null
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
+    }
+
+    #[test] fn local_definition () {
+        let ast = AST::VariableDefinition { name: Identifier::from("x"),
+                                         value: Box::new(AST::Number(1)) };
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::with_frame();
+
+        ast.compile_into(&mut program, &mut bookkeeping);
+
+        let expected_bookkeeping = Bookkeeping::from_locals(vec!("x".to_string()));
+
+        let expected_code = Code::from(vec!(
+            OpCode::Literal { index: ConstantPoolIndex::new(0) },    // value
+            OpCode::SetLocal { index: LocalFrameIndex::new(0) }
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::Integer(1)
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
+    }
+
+    #[test] fn global_definition () {
+        let ast = AST::VariableDefinition { name: Identifier::from("x"),
+            value: Box::new(AST::Number(1)) };
+
+        let mut program: Program = Program::empty();
+        let mut bookkeeping: Bookkeeping = Bookkeeping::without_frame();
+
+        ast.compile_into(&mut program, &mut bookkeeping);
+
+        let expected_bookkeeping = Bookkeeping::from_globals(vec!("x".to_string()));
+
+        let expected_code = Code::from(vec!(
+            OpCode::Literal { index: ConstantPoolIndex::new(1) },    // value
+            OpCode::SetGlobal { name: ConstantPoolIndex::new(0) },
+        ));
+
+        let expected_constants: Vec<ProgramObject> = vec!(
+            /* 0 */ ProgramObject::from_str("x"),
+            /* 1 */ ProgramObject::from_i32(1),
+        );
+
+        let expected_globals: Vec<ConstantPoolIndex> = vec!();
+        let expected_entry = ConstantPoolIndex::new(0);
+
+        let expected_program =
+            Program::new(expected_code, expected_constants, expected_globals, expected_entry);
+
+        assert_eq!(program, expected_program);
+        assert_eq!(bookkeeping, expected_bookkeeping);
     }
 
     #[test] fn local_access_x () {
@@ -3401,53 +5765,3 @@ mod compiler_tests {
         assert_eq!(bookkeeping, expected_bookkeeping);
     }
 }
-
-fn main() {
-    use std::env;
-    use std::fs::File;
-    use std::io::stdin;
-    use std::io::Read;
-    use fml_ast::AST;
-
-    use crate::program::Program;
-
-    println!("{:?}", env::args());
-
-    let files: Vec<String> = env::args().into_iter().map(|e| e.to_string()).collect();
-
-    let input = match files.len() {
-        0 => unreachable!(),
-        1 => {
-            let mut input = String::new();
-            stdin().read_to_string(&mut input).expect("Error reading from stdin");
-            input
-        },
-        2 => {
-            let path = files.last().unwrap();              // Cannot explode due to conditions above
-            let mut file = File::open(path).expect(&format!("Cannot open file: {}", path));
-            let mut input = String::new();
-            file.read_to_string(&mut input).expect(&format!("Cannot read file: {}", path));
-            input
-        },
-        n => {
-            panic!("Can only parse 1 file at a time, but the following files {} were provided: {:?}",
-                    n, files)
-        },
-    };
-
-    println!("{}", input);
-
-    let ast: AST = fml_parser::parse(&input).expect("Parse error");
-
-    println!("{:?}", ast);
-
-    let program: Program = compiler::compile(&ast);
-
-    println!("{:?}", program);
-
-    let mut source:Vec<u8> = Vec::new();
-    program.pretty_print(&mut source);
-    println!("{}", String::from_utf8(source).unwrap());
-
-    interpreter::evaluate(&program);
-}
\ No newline at end of file
@@ -0,0 +1,164 @@
+use std::fmt;
+
+use crate::bytecode::OpCode;
+use crate::inspect;
+use crate::interpreter::{interpret, State};
+use crate::program::Program;
+
+/// Bounds on what a program may use while it runs. `None` leaves a resource unbounded.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Limits {
+    /// Instructions executed.
+    pub instructions: Option<u64>,
+    /// Objects allocated in `Memory`. Nothing is ever freed, so every allocation counts.
+    pub objects: Option<usize>,
+    /// Elements of all arrays created, together.
+    pub elements: Option<usize>,
+    /// Depth of `State::frames`, including the frame of the entry method.
+    pub frames: Option<usize>,
+    /// Bytes of output.
+    pub output: Option<usize>,
+}
+
+impl Limits {
+    pub fn none() -> Self {
+        Limits::default()
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum LimitExceeded {
+    Instructions { limit: u64 },
+    Objects { limit: usize },
+    Elements { limit: usize },
+    Frames { limit: usize },
+    Output { limit: usize },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Instructions { limit } =>
+                write!(f, "program did not finish within {} instructions", limit),
+            LimitExceeded::Objects { limit } =>
+                write!(f, "program allocated more than {} objects", limit),
+            LimitExceeded::Elements { limit } =>
+                write!(f, "program created arrays of more than {} elements", limit),
+            LimitExceeded::Frames { limit } =>
+                write!(f, "program called more than {} frames deep", limit),
+            LimitExceeded::Output { limit } =>
+                write!(f, "program printed more than {} bytes", limit),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Runs the program in `state` to completion or until it exceeds one of `limits`. The state is
+/// left as it was after the last instruction executed, so it can still be inspected.
+pub fn run(program: &Program, state: &mut State, output: &mut String, limits: &Limits) -> Result<(), LimitExceeded> {
+    run_flushing(program, state, limits, |printed| output.push_str(printed))
+}
+
+/// Like `run`, but hands what each instruction printed to `flush` as soon as it executes, so
+/// that the output of a long run shows while it is still going.
+pub fn run_flushing<F: FnMut(&str)>(program: &Program, state: &mut State, limits: &Limits, mut flush: F)
+                                    -> Result<(), LimitExceeded> {
+    let mut meter = Meter::new(limits);
+    let mut output = String::new();
+    while state.instruction_pointer.is_some() {
+        let result = meter.step(program, state, &mut output);
+        if !output.is_empty() {
+            flush(&output);
+            output.clear();
+        }
+        result?;
+    }
+    Ok(())
+}
+
+/// Keeps count of what a program has used so far, one instruction at a time.
+pub struct Meter<'a> {
+    limits: &'a Limits,
+    executed: u64,
+    allocated: usize,
+    elements: usize,
+    printed: usize,
+}
+
+impl<'a> Meter<'a> {
+    pub fn new(limits: &'a Limits) -> Self {
+        Meter { limits, executed: 0, allocated: 0, elements: 0, printed: 0 }
+    }
+
+    /// The number of instructions executed so far.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
+
+    /// Executes one instruction unless that would go over the instruction limit or create an
+    /// array over the object or element limit, then checks the other limits. Output past the
+    /// limit is cut off. Printed bytes are counted as they are appended, so the caller may drain
+    /// `output` between steps.
+    pub fn step(&mut self, program: &Program, state: &mut State, output: &mut String) -> Result<(), LimitExceeded> {
+        if let Some(limit) = self.limits.instructions {
+            if self.executed >= limit {
+                return Err(LimitExceeded::Instructions { limit });
+            }
+        }
+        // A single `Array` can allocate any number of objects, so its size is checked up front.
+        let opcode = state.instruction_pointer.as_ref().and_then(|address| program.get_opcode(address));
+        if let Some(OpCode::Array) = opcode {
+            let size = self.array_size(state);
+            if let Some(limit) = self.limits.elements {
+                if self.elements.saturating_add(size) > limit {
+                    return Err(LimitExceeded::Elements { limit });
+                }
+            }
+            if let Some(limit) = self.limits.objects {
+                self.allocated = inspect::allocated(&state.memory, self.allocated);
+                // The elements and the array itself.
+                if self.allocated.saturating_add(size).saturating_add(1) > limit {
+                    return Err(LimitExceeded::Objects { limit });
+                }
+            }
+            self.elements = self.elements.saturating_add(size);
+        }
+
+        let before = output.len();
+        interpret(state, output, program);
+        self.executed += 1;
+        self.printed += output.len() - before;
+
+        if let Some(limit) = self.limits.objects {
+            self.allocated = inspect::allocated(&state.memory, self.allocated);
+            if self.allocated > limit {
+                return Err(LimitExceeded::Objects { limit });
+            }
+        }
+        if let Some(limit) = self.limits.frames {
+            if state.frames.len() > limit {
+                return Err(LimitExceeded::Frames { limit });
+            }
+        }
+        if let Some(limit) = self.limits.output {
+            if self.printed > limit {
+                let mut end = output.len().saturating_sub(self.printed - limit);
+                while !output.is_char_boundary(end) {
+                    end -= 1;
+                }
+                output.truncate(end);
+                return Err(LimitExceeded::Output { limit });
+            }
+        }
+        Ok(())
+    }
+
+    /// The size operand of an `Array` about to execute, which is below its initial value on the
+    /// stack. Anything but a positive integer is left for the interpreter to reject.
+    fn array_size(&self, state: &State) -> usize {
+        state.operands.len().checked_sub(2)
+            .and_then(|index| inspect::integer(&state.memory, &state.operands[index]))
+            .map_or(0, |size| size.max(0) as usize)
+    }
+}
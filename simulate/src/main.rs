@@ -0,0 +1,10 @@
+use std::env;
+use std::io;
+use std::process;
+
+use bytecode::cli;
+
+fn main() {
+    let arguments: Vec<String> = env::args().skip(1).collect();
+    process::exit(cli::main(&arguments, &mut io::stdout(), &mut io::stderr()));
+}
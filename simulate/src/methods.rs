@@ -0,0 +1,54 @@
+use crate::objects::ProgramObject;
+use crate::program::Program;
+
+/// A method in the constant pool together with the addresses its code covers.
+pub struct MethodRange {
+    /// Where the method is in the constant pool.
+    pub index: usize,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    /// Arguments and locals together.
+    pub frame_size: usize,
+}
+
+/// All methods in the constant pool, used to find the method that owns an address.
+pub struct Methods(Vec<MethodRange>);
+
+impl Methods {
+    pub fn from(program: &Program) -> Self {
+        Methods::from_constants(program.constants())
+    }
+
+    pub fn from_constants(constants: &[ProgramObject]) -> Self {
+        let methods = constants.iter().enumerate().filter_map(|(index, object)| match object {
+            ProgramObject::Method { name, arguments, locals, code } => {
+                let name = match constants.get(name.value() as usize) {
+                    Some(ProgramObject::String(name)) => name.clone(),
+                    _ => format!("#{}", name.value()),
+                };
+                let start = code.start().value_usize();
+                let frame_size = arguments.value() as usize + locals.value() as usize;
+                Some(MethodRange { index, name, start, end: start + code.length(), frame_size })
+            }
+            _ => None,
+        }).collect();
+        Methods(methods)
+    }
+
+    /// The innermost method covering `address`: functions compiled inside the code of another
+    /// method are attributed to the function.
+    pub fn at(&self, address: usize) -> Option<&MethodRange> {
+        self.0.iter()
+            .filter(|method| method.start <= address && address < method.end)
+            .min_by_key(|method| method.end - method.start)
+    }
+
+    pub fn named(&self, name: &str) -> Option<&MethodRange> {
+        self.0.iter().find(|method| method.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MethodRange> {
+        self.0.iter()
+    }
+}
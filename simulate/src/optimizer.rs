@@ -0,0 +1,663 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::bytecode::OpCode;
+use crate::methods::Methods;
+use crate::objects::ProgramObject;
+use crate::program::{Code, Program};
+use crate::types::{AddressRange, ConstantPoolIndex, LocalFrameIndex};
+
+/// Selects the passes run by `optimize`. Programs compiled with `none` are exactly what the
+/// compiler emitted.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Optimizations {
+    pub constant_folding: bool,
+    pub peephole: bool,
+    pub dead_code: bool,
+    pub tail_calls: bool,
+}
+
+impl Optimizations {
+    pub fn none() -> Self {
+        Optimizations { constant_folding: false, peephole: false, dead_code: false, tail_calls: false }
+    }
+
+    pub fn all() -> Self {
+        Optimizations { constant_folding: true, peephole: true, dead_code: true, tail_calls: true }
+    }
+}
+
+/// How many instructions each rewrite rule removed, and how many calls were turned into jumps.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Report {
+    removed: Vec<(&'static str, usize)>,
+    tail_calls: usize,
+}
+
+impl Report {
+    pub fn removed(&self, rule: &str) -> usize {
+        self.removed.iter()
+            .find(|(name, _)| *name == rule)
+            .map_or(0, |(_, removed)| *removed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.removed.iter().map(|(_, removed)| removed).sum()
+    }
+
+    pub fn tail_calls(&self) -> usize {
+        self.tail_calls
+    }
+
+    fn record(&mut self, rule: &'static str, removed: usize) {
+        if removed == 0 {
+            return;
+        }
+        match self.removed.iter_mut().find(|(name, _)| *name == rule) {
+            Some((_, total)) => *total += removed,
+            None => self.removed.push((rule, removed)),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (rule, removed) in self.removed.iter() {
+            writeln!(f, "{}: {} instructions removed", rule, removed)?;
+        }
+        if self.tail_calls > 0 {
+            writeln!(f, "tail calls: {} calls replaced with jumps", self.tail_calls)?;
+        }
+        write!(f, "total: {} instructions removed", self.total())
+    }
+}
+
+pub const FOLD_OPERATION: &str = "fold operation";
+pub const FOLD_BRANCH: &str = "fold branch";
+pub const STORE_AND_RELOAD: &str = "store and reload";
+pub const DISCARDED_VALUE: &str = "discarded value";
+pub const JUMP_TO_NEXT: &str = "jump to next instruction";
+pub const UNREACHABLE_CODE: &str = "unreachable code";
+pub const UNUSED_LABEL: &str = "unused label";
+
+pub fn optimize(program: &Program, optimizations: &Optimizations) -> Program {
+    optimize_with_report(program, optimizations).0
+}
+
+pub fn optimize_with_report(program: &Program, optimizations: &Optimizations) -> (Program, Report) {
+    let mut rewriter = Rewriter::from(program);
+    if optimizations.tail_calls {
+        rewriter.eliminate_tail_calls();
+    }
+    if optimizations.constant_folding {
+        rewriter.fold_constants();
+    }
+    if optimizations.dead_code {
+        rewriter.eliminate_dead_code();
+    }
+    if optimizations.peephole {
+        rewriter.peephole();
+        // Removing a jump to the next label can leave that label unused.
+        if optimizations.dead_code {
+            rewriter.eliminate_dead_code();
+        }
+    }
+    if optimizations.constant_folding || optimizations.peephole || optimizations.dead_code || optimizations.tail_calls {
+        rewriter.remove_unused_constants(!optimizations.dead_code);
+    }
+    let report = rewriter.report.clone();
+    (rewriter.finish(), report)
+}
+
+/// A program whose code is being rewritten. Every instruction remembers the address it had in
+/// the original program, so that method ranges can be recomputed once rewriting is done.
+struct Rewriter {
+    constants: Vec<ProgramObject>,
+    code: Vec<(usize, OpCode)>,
+    globals: Vec<ConstantPoolIndex>,
+    entry: ConstantPoolIndex,
+    /// Original addresses at which some method starts or ends. Instructions are never merged
+    /// across them.
+    boundaries: BTreeSet<usize>,
+    report: Report,
+}
+
+impl Rewriter {
+    fn from(program: &Program) -> Self {
+        let boundaries = program.constants().iter()
+            .filter_map(|object| match object {
+                ProgramObject::Method { code, .. } => Some(code),
+                _ => None,
+            })
+            .flat_map(|range| vec!(range.start().value_usize(), range.start().value_usize() + range.length()))
+            .collect();
+
+        Rewriter {
+            constants: program.constants().clone(),
+            code: program.code().as_vec().iter().cloned().enumerate().collect(),
+            globals: program.globals().iter().map(|index| ConstantPoolIndex::new(index.value())).collect(),
+            entry: ConstantPoolIndex::new(program.entry().value()),
+            boundaries,
+            report: Report::default(),
+        }
+    }
+
+    fn finish(self) -> Program {
+        let origins: Vec<usize> = self.code.iter().map(|(origin, _)| *origin).collect();
+        let relocate = |address: usize| origins.iter().filter(|origin| **origin < address).count();
+
+        let constants = self.constants.into_iter().map(|object| match object {
+            ProgramObject::Method { name, arguments, locals, code } => {
+                let start = relocate(code.start().value_usize());
+                let end = relocate(code.start().value_usize() + code.length());
+                ProgramObject::Method { name, arguments, locals, code: AddressRange::from(start, end - start) }
+            }
+            object => object,
+        }).collect();
+
+        let code = Code::from(self.code.into_iter().map(|(_, opcode)| opcode).collect());
+        Program::new(code, constants, self.globals, self.entry)
+    }
+
+    /// The index of `object` in the constant pool, adding it if it is not there yet.
+    fn constant(&mut self, object: ProgramObject) -> ConstantPoolIndex {
+        let index = match self.constants.iter().position(|existing| *existing == object) {
+            Some(index) => index,
+            None => {
+                self.constants.push(object);
+                self.constants.len() - 1
+            }
+        };
+        ConstantPoolIndex::new(index as u16)
+    }
+
+    /// True if no method starts or ends between the original addresses `from` and `to`. Code
+    /// inserted by a rewrite shares the address of the instruction it replaced or preceded, so
+    /// the two may be equal.
+    fn contiguous(&self, from: usize, to: usize) -> bool {
+        from >= to || self.boundaries.range(from + 1..=to).next().is_none()
+    }
+
+    /// The constant pushed by the instruction `back` places from the end of the rewritten code,
+    /// together with that instruction's original address.
+    fn literal(&self, back: usize) -> Option<(usize, &ProgramObject)> {
+        if back >= self.code.len() {
+            return None;
+        }
+        match &self.code[self.code.len() - 1 - back] {
+            (origin, OpCode::Literal { index }) =>
+                self.constants.get(index.value() as usize).map(|object| (*origin, object)),
+            _ => None,
+        }
+    }
+
+    /// Replaces operations on literals with their results and branches on literal conditions
+    /// with jumps or nothing at all. Folding happens as instructions are appended, so nested
+    /// operations collapse in a single pass.
+    fn fold_constants(&mut self) {
+        let code = std::mem::take(&mut self.code);
+        for (origin, opcode) in code {
+            let folded = match &opcode {
+                OpCode::CallMethod { name, arguments } if arguments.value() == 2 =>
+                    self.fold_operation(name, origin),
+                OpCode::Branch { label } =>
+                    self.fold_branch(label, origin),
+                _ => false,
+            };
+            if !folded {
+                self.code.push((origin, opcode));
+            }
+        }
+    }
+
+    fn fold_operation(&mut self, name: &ConstantPoolIndex, origin: usize) -> bool {
+        let operator = match self.constants.get(name.value() as usize) {
+            Some(ProgramObject::String(operator)) => operator,
+            _ => return false,
+        };
+        let (receiver_origin, result) = match (self.literal(1), self.literal(0)) {
+            (Some((receiver_origin, receiver)), Some((_, argument)))
+                if self.contiguous(receiver_origin, origin) => {
+                match operation(operator, receiver, argument) {
+                    Some(result) => (receiver_origin, result),
+                    None => return false,
+                }
+            }
+            _ => return false,
+        };
+
+        self.code.truncate(self.code.len() - 2);
+        let index = self.constant(result);
+        self.code.push((receiver_origin, OpCode::Literal { index }));
+        self.report.record(FOLD_OPERATION, 2);
+        true
+    }
+
+    fn fold_branch(&mut self, label: &ConstantPoolIndex, origin: usize) -> bool {
+        let (condition_origin, taken) = match self.literal(0) {
+            Some((condition_origin, condition)) if self.contiguous(condition_origin, origin) => {
+                match condition {
+                    ProgramObject::Boolean(value) => (condition_origin, *value),
+                    ProgramObject::Null => (condition_origin, false),
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        };
+
+        self.code.pop();
+        if taken {
+            self.code.push((condition_origin, OpCode::Jump { label: ConstantPoolIndex::new(label.value()) }));
+            self.report.record(FOLD_BRANCH, 1);
+        } else {
+            self.report.record(FOLD_BRANCH, 2);
+        }
+        true
+    }
+
+    /// Turns calls from a global function to itself that are immediately followed by a return
+    /// into a jump back to the start of the function, so that the recursion reuses the current
+    /// frame. The arguments are stored over the parameters, and the remaining locals are reset to
+    /// null as they would be in a new frame. Calls to a function whose name another global
+    /// shares are left alone, since they may not reach this method, and so are calls from
+    /// methods and functions compiled inside the function's range, which run in frames of
+    /// their own.
+    fn eliminate_tail_calls(&mut self) {
+        struct Function {
+            method: usize,
+            name: String,
+            arguments: u8,
+            locals: u16,
+            start: usize,
+        }
+
+        let name = |index: &ConstantPoolIndex| match self.constants.get(index.value() as usize) {
+            Some(ProgramObject::String(name)) => Some(name.clone()),
+            _ => None,
+        };
+        let global_names: Vec<Option<String>> = self.globals.iter()
+            .map(|global| match self.constants.get(global.value() as usize) {
+                Some(ProgramObject::Method { name: index, .. }) | Some(ProgramObject::Slot { name: index }) => name(index),
+                _ => None,
+            })
+            .collect();
+        let functions: Vec<Function> = self.globals.iter()
+            .filter_map(|global| match self.constants.get(global.value() as usize) {
+                Some(ProgramObject::Method { name: index, arguments, locals, code }) => name(index).map(|name| Function {
+                    method: global.value() as usize,
+                    name,
+                    arguments: arguments.value(),
+                    locals: locals.value(),
+                    start: code.start().value_usize(),
+                }),
+                _ => None,
+            })
+            .filter(|function| global_names.iter().filter(|global| global.as_ref() == Some(&function.name)).count() == 1)
+            .collect();
+
+        let methods = Methods::from_constants(&self.constants);
+
+        let tail_calls: BTreeMap<usize, usize> = self.code.windows(2).enumerate()
+            .filter_map(|(position, pair)| match (&pair[0], &pair[1]) {
+                ((origin, OpCode::CallFunction { name: callee, arguments }), (next, OpCode::Return))
+                    if self.contiguous(*origin, *next) => {
+                    let callee = name(callee)?;
+                    let caller = methods.at(*origin)?.index;
+                    functions.iter()
+                        .position(|function| {
+                            function.method == caller && function.name == callee && function.arguments == arguments.value()
+                        })
+                        .map(|function| (position, function))
+                }
+                _ => None,
+            })
+            .collect();
+        if tail_calls.is_empty() {
+            return;
+        }
+
+        let mut taken: HashSet<String> = self.code.iter()
+            .filter_map(|(_, opcode)| match opcode {
+                OpCode::Label { name: index } => name(index),
+                _ => None,
+            })
+            .collect();
+        let mut labels: BTreeMap<usize, ConstantPoolIndex> = BTreeMap::new();
+        for function in tail_calls.values() {
+            if labels.contains_key(function) {
+                continue;
+            }
+            let mut label = format!("{}:tail", functions[*function].name);
+            while taken.contains(&label) {
+                label.push('\'');
+            }
+            taken.insert(label.clone());
+            labels.insert(*function, self.constant(ProgramObject::String(label)));
+        }
+
+        let code = std::mem::take(&mut self.code);
+        let mut started: HashSet<usize> = HashSet::new();
+        let mut returning = false;
+        for (position, (origin, opcode)) in code.into_iter().enumerate() {
+            if returning {
+                returning = false;
+                continue;
+            }
+            for (function, label) in labels.iter() {
+                if functions[*function].start <= origin && started.insert(*function) {
+                    let name = ConstantPoolIndex::new(label.value());
+                    self.code.push((functions[*function].start, OpCode::Label { name }));
+                }
+            }
+            let function = match tail_calls.get(&position) {
+                Some(function) => &functions[*function],
+                None => {
+                    self.code.push((origin, opcode));
+                    continue;
+                }
+            };
+
+            let (arguments, locals) = (function.arguments as u16, function.locals);
+            for index in (0..arguments).rev() {
+                self.code.push((origin, OpCode::SetLocal { index: LocalFrameIndex::new(index) }));
+                self.code.push((origin, OpCode::Drop));
+            }
+            if locals > 0 {
+                let index = self.constant(ProgramObject::Null);
+                self.code.push((origin, OpCode::Literal { index }));
+                for index in arguments..arguments + locals {
+                    self.code.push((origin, OpCode::SetLocal { index: LocalFrameIndex::new(index) }));
+                }
+                self.code.push((origin, OpCode::Drop));
+            }
+            let label = ConstantPoolIndex::new(labels[&tail_calls[&position]].value());
+            self.code.push((origin, OpCode::Jump { label }));
+            self.report.tail_calls += 1;
+            returning = true;
+        }
+    }
+
+    /// Removes redundant instruction sequences. Like folding, rules are applied to the end of
+    /// the code as it is rebuilt, so a rewrite that exposes another pattern is handled as well.
+    fn peephole(&mut self) {
+        let code = std::mem::take(&mut self.code);
+        for instruction in code {
+            self.code.push(instruction);
+            while self.simplify_tail() {}
+        }
+    }
+
+    fn simplify_tail(&mut self) -> bool {
+        let length = self.code.len();
+        let last = match self.code.last() {
+            Some((origin, _)) => *origin,
+            None => return false,
+        };
+
+        // set local 1; drop; get local 1 => set local 1
+        if length >= 3 && self.contiguous(self.code[length - 3].0, last) {
+            let reloaded = match (&self.code[length - 3].1, &self.code[length - 2].1, &self.code[length - 1].1) {
+                (OpCode::SetLocal { index: stored }, OpCode::Drop, OpCode::GetLocal { index: loaded }) =>
+                    stored == loaded,
+                (OpCode::SetGlobal { name: stored }, OpCode::Drop, OpCode::GetGlobal { name: loaded }) =>
+                    stored == loaded,
+                _ => false,
+            };
+            if reloaded {
+                self.code.truncate(length - 2);
+                self.report.record(STORE_AND_RELOAD, 2);
+                return true;
+            }
+        }
+
+        // lit #13; drop => nothing
+        if length >= 2 && self.contiguous(self.code[length - 2].0, last) {
+            let discarded = matches!((&self.code[length - 2].1, &self.code[length - 1].1),
+                (OpCode::Literal { .. }, OpCode::Drop) | (OpCode::GetLocal { .. }, OpCode::Drop));
+            if discarded {
+                self.code.truncate(length - 2);
+                self.report.record(DISCARDED_VALUE, 2);
+                return true;
+            }
+        }
+
+        // goto #1; label #2; label #1 => label #2; label #1
+        if let OpCode::Label { name } = &self.code[length - 1].1 {
+            let jump = self.code[..length - 1].iter().enumerate().rev()
+                .find(|(_, (_, opcode))| !matches!(opcode, OpCode::Label { .. }))
+                .filter(|(_, (origin, opcode))| match opcode {
+                    OpCode::Jump { label } => label == name && self.contiguous(*origin, last),
+                    _ => false,
+                })
+                .map(|(position, _)| position);
+            if let Some(position) = jump {
+                self.code.remove(position);
+                self.report.record(JUMP_TO_NEXT, 1);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Drops instructions that cannot be reached from the entry method, from global functions
+    /// or from methods of classes that reachable code instantiates, as well as labels that no
+    /// reachable instruction jumps to.
+    fn eliminate_dead_code(&mut self) {
+        let labels: HashMap<u16, usize> = self.code.iter().enumerate()
+            .filter_map(|(position, (_, opcode))| match opcode {
+                OpCode::Label { name } => Some((name.value(), position)),
+                _ => None,
+            })
+            .collect();
+
+        let mut reachable = vec!(false; self.code.len());
+        let mut live = HashSet::new();
+        let mut methods: Vec<u16> = self.globals.iter().map(|index| index.value())
+            .chain(std::iter::once(self.entry.value()))
+            .collect();
+
+        while let Some(method) = methods.pop() {
+            if !live.insert(method) {
+                continue;
+            }
+            let (start, end) = match self.constants.get(method as usize) {
+                Some(ProgramObject::Method { code, .. }) => {
+                    let start = code.start().value_usize();
+                    (self.position(start), self.position(start + code.length()))
+                }
+                _ => continue,
+            };
+
+            let mut pending = vec!(start);
+            while let Some(position) = pending.pop() {
+                if position < start || position >= end || reachable[position] {
+                    continue;
+                }
+                reachable[position] = true;
+                match &self.code[position].1 {
+                    OpCode::Jump { label } => pending.extend(labels.get(&label.value())),
+                    OpCode::Branch { label } => {
+                        pending.extend(labels.get(&label.value()));
+                        pending.push(position + 1);
+                    }
+                    OpCode::Return => {}
+                    OpCode::Object { class } => {
+                        if let Some(ProgramObject::Class(members)) = self.constants.get(class.value() as usize) {
+                            methods.extend(members.iter().map(|member| member.value()));
+                        }
+                        pending.push(position + 1);
+                    }
+                    _ => pending.push(position + 1),
+                }
+            }
+        }
+
+        let targets: HashSet<u16> = self.code.iter().zip(reachable.iter())
+            .filter(|(_, reachable)| **reachable)
+            .filter_map(|((_, opcode), _)| match opcode {
+                OpCode::Jump { label } | OpCode::Branch { label } => Some(label.value()),
+                _ => None,
+            })
+            .collect();
+
+        let code = std::mem::take(&mut self.code);
+        let (mut unreachable, mut unused) = (0, 0);
+        for ((origin, opcode), reachable) in code.into_iter().zip(reachable) {
+            match &opcode {
+                _ if !reachable => unreachable += 1,
+                OpCode::Label { name } if !targets.contains(&name.value()) => unused += 1,
+                _ => self.code.push((origin, opcode)),
+            }
+        }
+        self.report.record(UNREACHABLE_CODE, unreachable);
+        self.report.record(UNUSED_LABEL, unused);
+    }
+
+    /// The position in the rewritten code of the first instruction at or after the original
+    /// `address`.
+    fn position(&self, address: usize) -> usize {
+        self.code.iter().filter(|(origin, _)| *origin < address).count()
+    }
+
+    /// Drops constants that nothing refers to any more and renumbers the rest. Unless
+    /// `keep_methods` is false, methods are kept even when unreferenced, since their code is
+    /// still part of the program.
+    fn remove_unused_constants(&mut self, keep_methods: bool) {
+        let mut used = vec!(false; self.constants.len());
+        let mut pending: Vec<u16> = self.globals.iter().map(|index| index.value())
+            .chain(std::iter::once(self.entry.value()))
+            .chain(self.code.iter().filter_map(|(_, opcode)| constant_operand(opcode)).map(|index| index.value()))
+            .chain(self.constants.iter().enumerate().filter_map(|(index, object)| match object {
+                ProgramObject::Method { .. } if keep_methods => Some(index as u16),
+                _ => None,
+            }))
+            .collect();
+
+        while let Some(index) = pending.pop() {
+            match used.get_mut(index as usize) {
+                Some(mark) if !*mark => *mark = true,
+                _ => continue,
+            }
+            pending.extend(constant_references(&self.constants[index as usize]).iter().map(|index| index.value()));
+        }
+
+        let mut renumbered: Vec<u16> = Vec::with_capacity(used.len());
+        let mut next: u16 = 0;
+        for mark in used.iter() {
+            renumbered.push(next);
+            if *mark {
+                next += 1;
+            }
+        }
+        let renumber = |index: &ConstantPoolIndex| match renumbered.get(index.value() as usize) {
+            Some(renumbered) => ConstantPoolIndex::new(*renumbered),
+            None => ConstantPoolIndex::new(index.value()),
+        };
+
+        let constants = std::mem::take(&mut self.constants);
+        self.constants = constants.into_iter().zip(used.iter())
+            .filter(|(_, used)| **used)
+            .map(|(object, _)| with_constant_references(object, renumber))
+            .collect();
+        let code = std::mem::take(&mut self.code);
+        self.code = code.into_iter()
+            .map(|(origin, opcode)| (origin, with_constant_operand(opcode, renumber)))
+            .collect();
+        self.globals = self.globals.iter().map(renumber).collect();
+        self.entry = renumber(&self.entry);
+    }
+}
+
+/// Evaluates a builtin operation the way the interpreter would, or returns `None` if the result
+/// cannot be known ahead of time. Arithmetic that overflows or divides by zero is left for the
+/// interpreter to deal with at run time.
+fn operation(operator: &str, receiver: &ProgramObject, argument: &ProgramObject) -> Option<ProgramObject> {
+    use ProgramObject::{Boolean, Integer, Null};
+    match (receiver, argument) {
+        (Integer(left), Integer(right)) => match operator {
+            "+" | "add" => left.checked_add(*right).map(Integer),
+            "-" | "sub" => left.checked_sub(*right).map(Integer),
+            "*" | "mul" => left.checked_mul(*right).map(Integer),
+            "/" | "div" => left.checked_div(*right).map(Integer),
+            "%" | "mod" => left.checked_rem(*right).map(Integer),
+            "==" | "eq" => Some(Boolean(left == right)),
+            "!=" | "neq" => Some(Boolean(left != right)),
+            "<" | "lt" => Some(Boolean(left < right)),
+            "<=" | "le" => Some(Boolean(left <= right)),
+            ">" | "gt" => Some(Boolean(left > right)),
+            ">=" | "ge" => Some(Boolean(left >= right)),
+            _ => None,
+        },
+        (Boolean(left), Boolean(right)) => match operator {
+            "&" | "and" => Some(Boolean(*left && *right)),
+            "|" | "or" => Some(Boolean(*left || *right)),
+            "==" | "eq" => Some(Boolean(left == right)),
+            "!=" | "neq" => Some(Boolean(left != right)),
+            _ => None,
+        },
+        (Null, Null) | (Null, Integer(_)) | (Null, Boolean(_)) | (Integer(_), Null) | (Boolean(_), Null) =>
+            match operator {
+                "==" | "eq" => Some(Boolean(receiver == argument)),
+                "!=" | "neq" => Some(Boolean(receiver != argument)),
+                _ => None,
+            },
+        _ => None,
+    }
+}
+
+fn constant_operand(opcode: &OpCode) -> Option<&ConstantPoolIndex> {
+    match opcode {
+        OpCode::Label { name } => Some(name),
+        OpCode::Literal { index } => Some(index),
+        OpCode::Print { format, .. } => Some(format),
+        OpCode::Object { class } => Some(class),
+        OpCode::GetSlot { name } => Some(name),
+        OpCode::SetSlot { name } => Some(name),
+        OpCode::CallMethod { name, .. } => Some(name),
+        OpCode::CallFunction { name, .. } => Some(name),
+        OpCode::SetGlobal { name } => Some(name),
+        OpCode::GetGlobal { name } => Some(name),
+        OpCode::Branch { label } => Some(label),
+        OpCode::Jump { label } => Some(label),
+        OpCode::Array | OpCode::SetLocal { .. } | OpCode::GetLocal { .. }
+        | OpCode::Return | OpCode::Drop | OpCode::Skip => None,
+    }
+}
+
+pub(crate) fn with_constant_operand<F: Fn(&ConstantPoolIndex) -> ConstantPoolIndex>(opcode: OpCode, f: F) -> OpCode {
+    match opcode {
+        OpCode::Label { name } => OpCode::Label { name: f(&name) },
+        OpCode::Literal { index } => OpCode::Literal { index: f(&index) },
+        OpCode::Print { format, arguments } => OpCode::Print { format: f(&format), arguments },
+        OpCode::Object { class } => OpCode::Object { class: f(&class) },
+        OpCode::GetSlot { name } => OpCode::GetSlot { name: f(&name) },
+        OpCode::SetSlot { name } => OpCode::SetSlot { name: f(&name) },
+        OpCode::CallMethod { name, arguments } => OpCode::CallMethod { name: f(&name), arguments },
+        OpCode::CallFunction { name, arguments } => OpCode::CallFunction { name: f(&name), arguments },
+        OpCode::SetGlobal { name } => OpCode::SetGlobal { name: f(&name) },
+        OpCode::GetGlobal { name } => OpCode::GetGlobal { name: f(&name) },
+        OpCode::Branch { label } => OpCode::Branch { label: f(&label) },
+        OpCode::Jump { label } => OpCode::Jump { label: f(&label) },
+        opcode => opcode,
+    }
+}
+
+fn constant_references(object: &ProgramObject) -> Vec<&ConstantPoolIndex> {
+    match object {
+        ProgramObject::Slot { name } => vec!(name),
+        ProgramObject::Method { name, .. } => vec!(name),
+        ProgramObject::Class(members) => members.iter().collect(),
+        _ => vec!(),
+    }
+}
+
+pub(crate) fn with_constant_references<F: Fn(&ConstantPoolIndex) -> ConstantPoolIndex>(object: ProgramObject, f: F) -> ProgramObject {
+    match object {
+        ProgramObject::Slot { name } => ProgramObject::Slot { name: f(&name) },
+        ProgramObject::Method { name, arguments, locals, code } =>
+            ProgramObject::Method { name: f(&name), arguments, locals, code },
+        ProgramObject::Class(members) => ProgramObject::Class(members.iter().map(f).collect()),
+        object => object,
+    }
+}
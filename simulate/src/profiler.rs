@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use crate::bytecode::OpCode;
+use crate::interpreter::{interpret, State};
+use crate::limits::{LimitExceeded, Limits, Meter};
+use crate::methods::Methods;
+use crate::program::Program;
+use crate::types::Address;
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct MethodStatistics {
+    pub calls: u64,
+    /// Instructions executed by the method itself and everything it called.
+    pub inclusive: u64,
+    /// Instructions executed by the method itself.
+    pub exclusive: u64,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CallSiteStatistics {
+    pub caller: String,
+    pub callee: String,
+    pub calls: u64,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Profile {
+    pub instructions: u64,
+    pub opcodes: HashMap<&'static str, u64>,
+    pub methods: HashMap<String, MethodStatistics>,
+    /// Keyed by the address of the `CallFunction` or `CallMethod` instruction.
+    pub call_sites: HashMap<usize, CallSiteStatistics>,
+    /// Exclusive instruction counts keyed by the call stack, outermost method first.
+    pub stacks: HashMap<Vec<String>, u64>,
+}
+
+/// Runs the program in `state` to completion, counting every instruction it executes.
+pub fn profile(program: &Program, state: &mut State, output: &mut String) -> Profile {
+    let (profile, _) = profile_steps(program, state, |state| {
+        interpret(state, output, program);
+        (true, Ok(()))
+    });
+    profile
+}
+
+/// Like `profile`, but stops once the program exceeds one of `limits`. The profile covers
+/// everything executed up to that point. What each instruction prints is handed to `flush` as
+/// soon as it executes, as `limits::run_flushing` does.
+pub fn profile_with_limits<F: FnMut(&str)>(program: &Program, state: &mut State, limits: &Limits, mut flush: F)
+                                           -> (Profile, Result<(), LimitExceeded>) {
+    let mut meter = Meter::new(limits);
+    let mut output = String::new();
+    profile_steps(program, state, |state| {
+        let before = meter.executed();
+        let result = meter.step(program, state, &mut output);
+        if !output.is_empty() {
+            flush(&output);
+            output.clear();
+        }
+        (meter.executed() > before, result)
+    })
+}
+
+/// Profiles the program, executing each instruction through `step`, which reports whether the
+/// instruction ran. A limit can be exceeded by an instruction that already ran, and that
+/// instruction is still counted.
+fn profile_steps<F>(program: &Program, state: &mut State, mut step: F) -> (Profile, Result<(), LimitExceeded>)
+    where F: FnMut(&mut State) -> (bool, Result<(), LimitExceeded>) {
+    let methods = Methods::from(program);
+    let mut profile = Profile::default();
+    let mut stack: Vec<String> = Vec::new();
+
+    if let Some(address) = &state.instruction_pointer {
+        stack.push(method_name(&methods, address.value_usize()));
+        profile.methods.entry(stack[0].clone()).or_default().calls += 1;
+    }
+
+    while let Some(address) = state.instruction_pointer.as_ref().map(|address| address.value_usize()) {
+        let opcode = program.get_opcode(&Address::from_usize(address)).map(mnemonic);
+        let depth = state.frames.len();
+
+        let (executed, result) = step(state);
+        if !executed {
+            return (profile, result);
+        }
+
+        profile.instructions += 1;
+        *profile.opcodes.entry(opcode.unwrap_or("?")).or_insert(0) += 1;
+        *profile.stacks.entry(stack.clone()).or_insert(0) += 1;
+        if let Some(current) = stack.last() {
+            profile.methods.entry(current.clone()).or_default().exclusive += 1;
+        }
+        let mut counted = HashSet::new();
+        for method in stack.iter() {
+            if counted.insert(method) {
+                profile.methods.entry(method.clone()).or_default().inclusive += 1;
+            }
+        }
+
+        if state.frames.len() > depth {
+            let callee = match &state.instruction_pointer {
+                Some(target) => method_name(&methods, target.value_usize()),
+                None => "?".to_string(),
+            };
+            let caller = stack.last().cloned().unwrap_or_else(|| "?".to_string());
+            profile.methods.entry(callee.clone()).or_default().calls += 1;
+            profile.call_sites.entry(address)
+                .or_insert(CallSiteStatistics { caller, callee: callee.clone(), calls: 0 })
+                .calls += 1;
+            stack.push(callee);
+        } else if state.frames.len() < depth {
+            stack.pop();
+        }
+
+        if result.is_err() {
+            return (profile, result);
+        }
+    }
+
+    (profile, Ok(()))
+}
+
+fn method_name(methods: &Methods, address: usize) -> String {
+    match methods.at(address) {
+        Some(method) => method.name.clone(),
+        None => format!("<{}>", address),
+    }
+}
+
+fn mnemonic(opcode: &OpCode) -> &'static str {
+    match opcode {
+        OpCode::Label { .. } => "Label",
+        OpCode::Literal { .. } => "Literal",
+        OpCode::Print { .. } => "Print",
+        OpCode::Array => "Array",
+        OpCode::Object { .. } => "Object",
+        OpCode::GetSlot { .. } => "GetSlot",
+        OpCode::SetSlot { .. } => "SetSlot",
+        OpCode::CallMethod { .. } => "CallMethod",
+        OpCode::CallFunction { .. } => "CallFunction",
+        OpCode::SetLocal { .. } => "SetLocal",
+        OpCode::GetLocal { .. } => "GetLocal",
+        OpCode::SetGlobal { .. } => "SetGlobal",
+        OpCode::GetGlobal { .. } => "GetGlobal",
+        OpCode::Branch { .. } => "Branch",
+        OpCode::Jump { .. } => "Jump",
+        OpCode::Return => "Return",
+        OpCode::Drop => "Drop",
+        OpCode::Skip => "Skip",
+    }
+}
+
+impl Profile {
+    /// Writes the profile as tables sorted from the most to the least expensive entry.
+    pub fn table<W: Write>(&self, sink: &mut W) -> io::Result<()> {
+        writeln!(sink, "instructions executed: {}", self.instructions)?;
+
+        writeln!(sink)?;
+        writeln!(sink, "{:<16} {:>12}", "opcode", "executed")?;
+        let mut opcodes: Vec<(&&str, &u64)> = self.opcodes.iter().collect();
+        opcodes.sort_by(|(left_name, left), (right_name, right)| right.cmp(left).then(left_name.cmp(right_name)));
+        for (name, count) in opcodes {
+            writeln!(sink, "{:<16} {:>12}", name, count)?;
+        }
+
+        writeln!(sink)?;
+        writeln!(sink, "{:<24} {:>8} {:>12} {:>12}", "method", "calls", "inclusive", "exclusive")?;
+        let mut methods: Vec<(&String, &MethodStatistics)> = self.methods.iter().collect();
+        methods.sort_by(|(left_name, left), (right_name, right)| {
+            right.inclusive.cmp(&left.inclusive).then(left_name.cmp(right_name))
+        });
+        for (name, statistics) in methods {
+            writeln!(sink, "{:<24} {:>8} {:>12} {:>12}",
+                     name, statistics.calls, statistics.inclusive, statistics.exclusive)?;
+        }
+
+        writeln!(sink)?;
+        writeln!(sink, "{:<8} {:<24} {:<24} {:>8}", "address", "caller", "callee", "calls")?;
+        let mut call_sites: Vec<(&usize, &CallSiteStatistics)> = self.call_sites.iter().collect();
+        call_sites.sort_by(|(left_address, left), (right_address, right)| {
+            right.calls.cmp(&left.calls).then(left_address.cmp(right_address))
+        });
+        for (address, site) in call_sites {
+            writeln!(sink, "{:<8} {:<24} {:<24} {:>8}", address, site.caller, site.callee, site.calls)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one `outer;inner count` line per call stack, the input format of flame graph
+    /// tools such as `flamegraph.pl` and `inferno`.
+    pub fn folded<W: Write>(&self, sink: &mut W) -> io::Result<()> {
+        let mut stacks: Vec<(String, u64)> = self.stacks.iter()
+            .map(|(stack, count)| (stack.join(";"), *count))
+            .collect();
+        stacks.sort();
+        for (stack, count) in stacks {
+            writeln!(sink, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bytecode::OpCode;
+use crate::methods::Methods;
+use crate::objects::ProgramObject;
+use crate::program::Program;
+use crate::types::{Address, ConstantPoolIndex, LocalFrameIndex};
+
+/// Checks a `Program` for everything the interpreter takes for granted, so that a malformed
+/// program is rejected up front instead of panicking halfway through a run.
+pub fn verify(program: &Program) -> Result<(), Vec<Diagnostic>> {
+    let mut verifier = Verifier::new(program);
+    verifier.constants();
+    verifier.globals();
+    verifier.entry();
+    verifier.labels();
+    verifier.instructions();
+    verifier.stacks();
+
+    if verifier.diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(verifier.diagnostics)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct Diagnostic {
+    /// Name of the method containing the problem, if it is inside one.
+    pub method: Option<String>,
+    pub address: Option<Address>,
+    pub problem: Problem,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Problem {
+    ConstantOutOfBounds { index: ConstantPoolIndex },
+    WrongConstantKind { index: ConstantPoolIndex, expected: &'static str, found: &'static str },
+    MethodOutOfBounds { start: usize, length: usize, code_size: usize },
+    UndefinedLabel { name: String },
+    DuplicateLabel { name: String },
+    LabelOutsideMethod { name: String },
+    LocalOutOfBounds { index: LocalFrameIndex, frame_size: usize },
+    FormatArity { placeholders: usize, arguments: usize },
+    StackUnderflow { depth: usize, required: usize },
+    InconsistentStack { expected: usize, found: usize },
+    ReturnDepth { depth: usize },
+    FallsOffEnd,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.method, &self.address) {
+            (Some(method), Some(address)) => write!(f, "in method `{}` at {}: ", method, address.value_usize())?,
+            (Some(method), None) => write!(f, "in method `{}`: ", method)?,
+            (None, Some(address)) => write!(f, "at {}: ", address.value_usize())?,
+            (None, None) => {},
+        }
+        match &self.problem {
+            Problem::ConstantOutOfBounds { index } =>
+                write!(f, "constant #{} is outside the constant pool", index.value()),
+            Problem::WrongConstantKind { index, expected, found } =>
+                write!(f, "constant #{} should be {} but is {}", index.value(), expected, found),
+            Problem::MethodOutOfBounds { start, length, code_size } =>
+                write!(f, "code range {}..{} overruns code of size {}", start, start + length, code_size),
+            Problem::UndefinedLabel { name } =>
+                write!(f, "label `{}` is not defined", name),
+            Problem::DuplicateLabel { name } =>
+                write!(f, "label `{}` is defined more than once", name),
+            Problem::LabelOutsideMethod { name } =>
+                write!(f, "label `{}` is outside of the method", name),
+            Problem::LocalOutOfBounds { index, frame_size } =>
+                write!(f, "local {} is outside of a frame of size {}", index.value(), frame_size),
+            Problem::FormatArity { placeholders, arguments } =>
+                write!(f, "format has {} placeholders but {} arguments are printed", placeholders, arguments),
+            Problem::StackUnderflow { depth, required } =>
+                write!(f, "instruction needs {} operands but the stack holds {}", required, depth),
+            Problem::InconsistentStack { expected, found } =>
+                write!(f, "stack holds {} operands on one path and {} on another", expected, found),
+            Problem::ReturnDepth { depth } =>
+                write!(f, "return with {} operands on the stack instead of 1", depth),
+            Problem::FallsOffEnd =>
+                write!(f, "execution can run past the end of the method"),
+        }
+    }
+}
+
+fn kind(object: &ProgramObject) -> &'static str {
+    match object {
+        ProgramObject::Null => "Null",
+        ProgramObject::Integer(_) => "Integer",
+        ProgramObject::Boolean(_) => "Boolean",
+        ProgramObject::String(_) => "String",
+        ProgramObject::Slot { .. } => "Slot",
+        ProgramObject::Method { .. } => "Method",
+        ProgramObject::Class(_) => "Class",
+    }
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    code: &'a Vec<OpCode>,
+    constants: &'a Vec<ProgramObject>,
+    methods: Methods,
+    labels: HashMap<String, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: &'a Program) -> Self {
+        let code = program.code().as_vec();
+        let constants = program.constants();
+        let methods = Methods::from(program);
+        Verifier { program, code, constants, methods, labels: HashMap::new(), diagnostics: Vec::new() }
+    }
+
+    fn report(&mut self, address: Option<usize>, problem: Problem) {
+        let method = address.and_then(|address| self.methods.at(address)).map(|method| method.name.clone());
+        let address = address.map(Address::from_usize);
+        self.diagnostics.push(Diagnostic { method, address, problem });
+    }
+
+    fn report_in(&mut self, method: &str, address: Option<usize>, problem: Problem) {
+        let method = Some(method.to_string());
+        let address = address.map(Address::from_usize);
+        self.diagnostics.push(Diagnostic { method, address, problem });
+    }
+
+    /// Looks up a constant and checks that it is one of the `expected` kinds, written as
+    /// for example `"Slot or Method"`.
+    fn expect(&mut self, address: Option<usize>, index: &ConstantPoolIndex,
+              expected: &'static str) -> Option<&'a ProgramObject> {
+        match self.constants.get(index.value() as usize) {
+            None => {
+                self.report(address, Problem::ConstantOutOfBounds { index: ConstantPoolIndex::new(index.value()) });
+                None
+            }
+            Some(object) if expected.split(" or ").any(|expected| expected == kind(object)) => Some(object),
+            Some(object) => {
+                let problem = Problem::WrongConstantKind {
+                    index: ConstantPoolIndex::new(index.value()),
+                    expected,
+                    found: kind(object),
+                };
+                self.report(address, problem);
+                None
+            }
+        }
+    }
+
+    fn expect_string(&mut self, address: Option<usize>, index: &ConstantPoolIndex) -> Option<&'a String> {
+        match self.expect(address, index, "String") {
+            Some(ProgramObject::String(string)) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn constants(&mut self) {
+        for object in self.constants {
+            match object {
+                ProgramObject::Slot { name } => {
+                    self.expect_string(None, name);
+                }
+                ProgramObject::Class(members) => {
+                    for member in members {
+                        self.expect(None, member, "Slot or Method");
+                    }
+                }
+                ProgramObject::Method { name, code, .. } => {
+                    self.expect_string(None, name);
+                    let start = code.start().value_usize();
+                    let length = code.length();
+                    if start + length > self.code.len() {
+                        let problem = Problem::MethodOutOfBounds { start, length, code_size: self.code.len() };
+                        self.report(None, problem);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn globals(&mut self) {
+        for global in self.program.globals() {
+            self.expect(None, global, "Method or Slot");
+        }
+    }
+
+    fn entry(&mut self) {
+        self.expect(None, self.program.entry(), "Method");
+    }
+
+    fn labels(&mut self) {
+        for (address, opcode) in self.code.iter().enumerate() {
+            if let OpCode::Label { name } = opcode {
+                if let Some(name) = self.expect_string(Some(address), name) {
+                    if self.labels.contains_key(name) {
+                        self.report(Some(address), Problem::DuplicateLabel { name: name.clone() });
+                    } else {
+                        self.labels.insert(name.clone(), address);
+                    }
+                }
+            }
+        }
+    }
+
+    fn instructions(&mut self) {
+        for (address, opcode) in self.code.iter().enumerate() {
+            let here = Some(address);
+            match opcode {
+                OpCode::Literal { index } => {
+                    self.expect(here, index, "Null or Integer or Boolean");
+                }
+                OpCode::Print { format, arguments } => {
+                    if let Some(format) = self.expect_string(here, format) {
+                        let placeholders = format.matches('~').count();
+                        let arguments = arguments.value() as usize;
+                        if placeholders != arguments {
+                            self.report(here, Problem::FormatArity { placeholders, arguments });
+                        }
+                    }
+                }
+                OpCode::Object { class } => {
+                    self.expect(here, class, "Class");
+                }
+                OpCode::GetSlot { name } | OpCode::SetSlot { name }
+                | OpCode::CallMethod { name, .. } | OpCode::CallFunction { name, .. }
+                | OpCode::GetGlobal { name } | OpCode::SetGlobal { name } => {
+                    self.expect_string(here, name);
+                }
+                OpCode::GetLocal { index } | OpCode::SetLocal { index } => {
+                    if let Some(frame_size) = self.methods.at(address).map(|method| method.frame_size) {
+                        if index.value() as usize >= frame_size {
+                            let index = LocalFrameIndex::new(index.value());
+                            self.report(here, Problem::LocalOutOfBounds { index, frame_size });
+                        }
+                    }
+                }
+                OpCode::Jump { label } | OpCode::Branch { label } => {
+                    if let Some(name) = self.expect_string(here, label) {
+                        match self.labels.get(name) {
+                            None => self.report(here, Problem::UndefinedLabel { name: name.clone() }),
+                            Some(target) => {
+                                let target = *target;
+                                let outside = match self.methods.at(address) {
+                                    Some(method) => target < method.start || target >= method.end,
+                                    None => false,
+                                };
+                                if outside {
+                                    self.report(here, Problem::LabelOutsideMethod { name: name.clone() });
+                                }
+                            }
+                        }
+                    }
+                }
+                OpCode::Label { .. } | OpCode::Array | OpCode::Return | OpCode::Drop | OpCode::Skip => {}
+            }
+        }
+    }
+
+    /// Walks every path through each method, tracking how many operands the method has pushed.
+    /// Each method has to reach `Return` with exactly its result on the stack.
+    fn stacks(&mut self) {
+        let methods: Vec<(String, usize, usize)> = self.methods.iter()
+            .filter(|method| method.end <= self.code.len())
+            .map(|method| (method.name.clone(), method.start, method.end))
+            .collect();
+
+        for (name, start, end) in methods {
+            let mut depths: HashMap<usize, usize> = HashMap::new();
+            let mut worklist: Vec<(usize, usize)> = vec!((start, 0));
+
+            while let Some((address, depth)) = worklist.pop() {
+                if address >= end {
+                    // An empty method has no last instruction to point at.
+                    let last = if end > start { Some(end - 1) } else { None };
+                    self.report_in(&name, last, Problem::FallsOffEnd);
+                    continue;
+                }
+                match depths.get(&address) {
+                    Some(expected) if *expected == depth => continue,
+                    Some(expected) => {
+                        let problem = Problem::InconsistentStack { expected: *expected, found: depth };
+                        self.report_in(&name, Some(address), problem);
+                        continue;
+                    }
+                    None => { depths.insert(address, depth); }
+                }
+
+                let opcode = &self.code[address];
+                let (pops, pushes) = self.stack_effect(opcode);
+                if depth < pops {
+                    self.report_in(&name, Some(address), Problem::StackUnderflow { depth, required: pops });
+                    continue;
+                }
+                let depth = depth - pops + pushes;
+
+                match opcode {
+                    OpCode::Return => {
+                        if depth != 0 {
+                            self.report_in(&name, Some(address), Problem::ReturnDepth { depth: depth + 1 });
+                        }
+                    }
+                    OpCode::Jump { label } => {
+                        if let Some(target) = self.target(label, start, end) {
+                            worklist.push((target, depth));
+                        }
+                    }
+                    OpCode::Branch { label } => {
+                        if let Some(target) = self.target(label, start, end) {
+                            worklist.push((target, depth));
+                        }
+                        worklist.push((address + 1, depth));
+                    }
+                    _ => worklist.push((address + 1, depth)),
+                }
+            }
+        }
+    }
+
+    /// Resolves a jump target, leaving paths that leave the method to `instructions`.
+    fn target(&self, label: &ConstantPoolIndex, start: usize, end: usize) -> Option<usize> {
+        match self.constants.get(label.value() as usize) {
+            Some(ProgramObject::String(name)) => self.labels.get(name)
+                .cloned()
+                .filter(|target| start <= *target && *target < end),
+            _ => None,
+        }
+    }
+
+    fn stack_effect(&self, opcode: &OpCode) -> (usize, usize) {
+        match opcode {
+            OpCode::Label { .. } | OpCode::Jump { .. } | OpCode::Skip => (0, 0),
+            OpCode::Literal { .. } | OpCode::GetLocal { .. } | OpCode::GetGlobal { .. } => (0, 1),
+            OpCode::SetLocal { .. } | OpCode::SetGlobal { .. } | OpCode::GetSlot { .. } => (1, 1),
+            OpCode::SetSlot { .. } | OpCode::Array => (2, 1),
+            OpCode::Print { arguments, .. }
+            | OpCode::CallMethod { arguments, .. }
+            | OpCode::CallFunction { arguments, .. } => (arguments.value() as usize, 1),
+            OpCode::Object { class } => {
+                let slots = match self.constants.get(class.value() as usize) {
+                    Some(ProgramObject::Class(members)) => members.iter()
+                        .filter(|member| matches!(self.constants.get(member.value() as usize),
+                                                  Some(ProgramObject::Slot { .. })))
+                        .count(),
+                    _ => 0,
+                };
+                (slots + 1, 1)
+            }
+            OpCode::Branch { .. } | OpCode::Drop | OpCode::Return => (1, 0),
+        }
+    }
+}